@@ -0,0 +1,906 @@
+use crate::chip8::{MEMORY_SIZE, PROGRAM_START};
+use crate::instruction::Instruction;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_ADDRESS: i64 = 0xFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for AsmError {}
+
+/// Where the bytes at `address` came from in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineInfo {
+    pub address: u16,
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>,
+    pub lines: Vec<LineInfo>,
+}
+
+/// Assemble source held in memory. Includes are resolved relative to the working directory.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler::new();
+    assembler.parse_source("<input>", source, Path::new("."), 0)?;
+    assembler.finish()
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Assembly, AsmError> {
+    let path = path.as_ref();
+    let mut assembler = Assembler::new();
+    assembler.parse_file(path, None, 0)?;
+    assembler.finish()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    LBracket,
+    RBracket,
+    Plus,
+    Minus,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error<S: Into<String>>(&self, column: usize, message: S) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+enum StatementKind {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Vec<Token>>,
+    },
+    Db(Vec<Vec<Token>>),
+    Dw(Vec<Vec<Token>>),
+}
+
+struct Statement {
+    address: u16,
+    location: Location,
+    column: usize,
+    kind: StatementKind,
+}
+
+struct Assembler {
+    address: i64,
+    symbols: BTreeMap<String, i64>,
+    statements: Vec<Statement>,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            address: PROGRAM_START as i64,
+            symbols: BTreeMap::new(),
+            statements: Vec::new(),
+        }
+    }
+
+    fn parse_file(
+        &mut self,
+        path: &Path,
+        included_from: Option<(&Location, usize)>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let source = fs::read_to_string(path).map_err(|err| {
+            let message = format!("unable to read {}: {}", path.display(), err);
+            match included_from {
+                Some((location, column)) => location.error(column, message),
+                None => AsmError {
+                    file: path.display().to_string(),
+                    line: 0,
+                    column: 0,
+                    message,
+                },
+            }
+        })?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+
+        self.parse_source(&path.display().to_string(), &source, directory, depth)
+    }
+
+    fn parse_source(
+        &mut self,
+        file: &str,
+        source: &str,
+        directory: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (index, text) in source.lines().enumerate() {
+            let location = Location {
+                file: file.to_string(),
+                line: index + 1,
+            };
+            let tokens = tokenize(text, &location)?;
+            self.parse_line(tokens, location, directory, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_line(
+        &mut self,
+        mut tokens: Vec<Token>,
+        location: Location,
+        directory: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        if tokens.len() >= 2 && tokens[1].kind == TokenKind::Colon {
+            if let TokenKind::Ident(name) = &tokens[0].kind {
+                let value = self.address;
+                self.define(name, value, &location, tokens[0].column)?;
+                tokens.drain(..2);
+            }
+        }
+
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        let (word, column) = match &tokens[0].kind {
+            TokenKind::Ident(word) => (word.clone(), tokens[0].column),
+            _ => return Err(location.error(tokens[0].column, "expected a mnemonic or directive")),
+        };
+
+        if tokens.len() >= 2 {
+            if let TokenKind::Ident(directive) = &tokens[1].kind {
+                if directive.eq_ignore_ascii_case("equ") {
+                    if tokens.len() == 2 {
+                        return Err(location.error(tokens[1].column, "expected a value"));
+                    }
+                    let value = self.evaluate(&tokens[2..], &location, tokens[1].column)?;
+                    return self.define(&word, value, &location, column);
+                }
+            }
+        }
+
+        let operands = split_operands(&tokens[1..], &location)?;
+        let upper = word.to_ascii_uppercase();
+
+        let (kind, size) = match upper.as_str() {
+            "INCLUDE" => return self.include(&operands, &location, column, directory, depth),
+            "DB" => {
+                let mut size = 0;
+                for operand in &operands {
+                    size += match operand.as_slice() {
+                        [Token {
+                            kind: TokenKind::Str(text),
+                            ..
+                        }] => text.len(),
+                        _ => 1,
+                    };
+                }
+                (StatementKind::Db(operands), size)
+            }
+            "DW" => {
+                let size = operands.len() * 2;
+                (StatementKind::Dw(operands), size)
+            }
+            _ => {
+                let size = if is_long(&operands) { 4 } else { 2 };
+                let kind = StatementKind::Instruction {
+                    mnemonic: upper,
+                    operands,
+                };
+                (kind, size)
+            }
+        };
+
+        if self.address + size as i64 > MEMORY_SIZE as i64 {
+            return Err(location.error(column, "program does not fit in memory"));
+        }
+
+        self.statements.push(Statement {
+            address: self.address as u16,
+            location,
+            column,
+            kind,
+        });
+        self.address += size as i64;
+
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        operands: &[Vec<Token>],
+        location: &Location,
+        column: usize,
+        directory: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let path = match operands {
+            [operand] => match operand.as_slice() {
+                [Token {
+                    kind: TokenKind::Str(path),
+                    ..
+                }] => path,
+                _ => return Err(location.error(column, "include expects a quoted file name")),
+            },
+            _ => return Err(location.error(column, "include expects a quoted file name")),
+        };
+
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error(column, "includes are nested too deeply"));
+        }
+
+        let path: PathBuf = directory.join(path);
+        self.parse_file(&path, Some((location, column)), depth + 1)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        value: i64,
+        location: &Location,
+        column: usize,
+    ) -> Result<(), AsmError> {
+        if parse_register(name).is_some() || is_reserved(name) {
+            return Err(location.error(column, format!("'{}' is a reserved name", name)));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(location.error(column, format!("'{}' is already defined", name)));
+        }
+
+        self.symbols.insert(name.to_string(), value);
+
+        Ok(())
+    }
+
+    fn evaluate(
+        &self,
+        tokens: &[Token],
+        location: &Location,
+        column: usize,
+    ) -> Result<i64, AsmError> {
+        if tokens.is_empty() {
+            return Err(location.error(column, "expected a value"));
+        }
+
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut expect_term = true;
+
+        for token in tokens {
+            let value = match &token.kind {
+                TokenKind::Plus if expect_term => continue,
+                TokenKind::Minus if expect_term => {
+                    sign = -sign;
+                    continue;
+                }
+                TokenKind::Plus => {
+                    sign = 1;
+                    expect_term = true;
+                    continue;
+                }
+                TokenKind::Minus => {
+                    sign = -1;
+                    expect_term = true;
+                    continue;
+                }
+                TokenKind::Number(value) if expect_term => *value,
+                TokenKind::Ident(name) if expect_term => {
+                    *self.symbols.get(name).ok_or_else(|| {
+                        location.error(token.column, format!("undefined symbol '{}'", name))
+                    })?
+                }
+                _ => return Err(location.error(token.column, "unexpected token in expression")),
+            };
+
+            total = sign
+                .checked_mul(value)
+                .and_then(|value| total.checked_add(value))
+                .ok_or_else(|| location.error(token.column, "value is too large"))?;
+            expect_term = false;
+        }
+
+        if expect_term {
+            let last = &tokens[tokens.len() - 1];
+            return Err(location.error(last.column, "expression ends with an operator"));
+        }
+
+        Ok(total)
+    }
+
+    fn finish(self) -> Result<Assembly, AsmError> {
+        let mut rom = Vec::new();
+        let mut lines = Vec::new();
+
+        for statement in &self.statements {
+            lines.push(LineInfo {
+                address: statement.address,
+                file: statement.location.file.clone(),
+                line: statement.location.line,
+            });
+
+            match &statement.kind {
+                StatementKind::Instruction { mnemonic, operands } => {
+                    let instruction = self.encode(mnemonic, operands, statement)?;
+                    rom.extend_from_slice(&instruction.to_bytes());
+                }
+                StatementKind::Db(values) => {
+                    for value in values {
+                        match value.as_slice() {
+                            [Token {
+                                kind: TokenKind::Str(text),
+                                ..
+                            }] => rom.extend_from_slice(text.as_bytes()),
+                            _ => {
+                                let byte = self.byte(value, statement)?;
+                                rom.push(byte);
+                            }
+                        }
+                    }
+                }
+                StatementKind::Dw(values) => {
+                    for value in values {
+                        let column = value.first().map_or(statement.column, |t| t.column);
+                        let word = self.evaluate(value, &statement.location, column)?;
+                        if !(-0x8000..=0xFFFF).contains(&word) {
+                            return Err(statement
+                                .location
+                                .error(column, format!("value {} does not fit in a word", word)));
+                        }
+                        rom.extend_from_slice(&(word as u16).to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        let symbols = self
+            .symbols
+            .iter()
+            .map(|(name, value)| (name.clone(), *value as u16))
+            .collect();
+
+        Ok(Assembly {
+            rom,
+            symbols,
+            lines,
+        })
+    }
+
+    fn byte(&self, tokens: &[Token], statement: &Statement) -> Result<u8, AsmError> {
+        let column = tokens.first().map_or(statement.column, |t| t.column);
+        let value = self.evaluate(tokens, &statement.location, column)?;
+        if !(-0x80..=0xFF).contains(&value) {
+            return Err(statement
+                .location
+                .error(column, format!("value {} does not fit in a byte", value)));
+        }
+
+        Ok(value as u8)
+    }
+
+    fn operand(&self, tokens: &[Token], statement: &Statement) -> Result<Operand, AsmError> {
+        let column = tokens.first().map_or(statement.column, |t| t.column);
+
+        if let [Token {
+            kind: TokenKind::Ident(name),
+            ..
+        }] = tokens
+        {
+            if let Some(register) = parse_register(name) {
+                return Ok(Operand::Register(register));
+            }
+            match name.to_ascii_uppercase().as_str() {
+                "I" => return Ok(Operand::I),
+                "DT" => return Ok(Operand::Dt),
+                "ST" => return Ok(Operand::St),
+                "K" => return Ok(Operand::K),
+                "F" => return Ok(Operand::F),
+                "HF" => return Ok(Operand::Hf),
+                "R" => return Ok(Operand::R),
+                "B" => return Ok(Operand::B),
+                _ => (),
+            }
+        }
+
+        if let [Token {
+            kind: TokenKind::Ident(name),
+            ..
+        }, rest @ ..] = tokens
+        {
+            if name.eq_ignore_ascii_case("LONG") {
+                let column = rest.first().map_or(column, |t| t.column);
+                let value = self.evaluate(rest, &statement.location, column)?;
+                return Ok(Operand::Long(value, column));
+            }
+        }
+
+        if let [Token {
+            kind: TokenKind::LBracket,
+            ..
+        }, Token {
+            kind: TokenKind::Ident(name),
+            ..
+        }, Token {
+            kind: TokenKind::RBracket,
+            ..
+        }] = tokens
+        {
+            if name.eq_ignore_ascii_case("I") {
+                return Ok(Operand::IndirectI);
+            }
+        }
+
+        let value = self.evaluate(tokens, &statement.location, column)?;
+        Ok(Operand::Value(value, column))
+    }
+
+    fn encode(
+        &self,
+        mnemonic: &str,
+        operands: &[Vec<Token>],
+        statement: &Statement,
+    ) -> Result<Instruction, AsmError> {
+        let operands = operands
+            .iter()
+            .map(|tokens| self.operand(tokens, statement))
+            .collect::<Result<Vec<Operand>, AsmError>>()?;
+        let location = &statement.location;
+
+        let long_address = |value: i64, column: usize| {
+            if !(0..MEMORY_SIZE as i64).contains(&value) {
+                Err(location.error(column, format!("address {:#X} is out of range", value)))
+            } else {
+                Ok(value as u16)
+            }
+        };
+        let planes = |value: i64, column: usize| {
+            if !(0..=3).contains(&value) {
+                Err(location.error(column, format!("plane mask {} must be 0 to 3", value)))
+            } else {
+                Ok(value as u8)
+            }
+        };
+        let address = |value: i64, column: usize| {
+            if !(0..=MAX_ADDRESS).contains(&value) {
+                Err(location.error(column, format!("address {:#X} is out of range", value)))
+            } else {
+                Ok(value as u16)
+            }
+        };
+        let byte = |value: i64, column: usize| {
+            if !(-0x80..=0xFF).contains(&value) {
+                Err(location.error(column, format!("value {} does not fit in a byte", value)))
+            } else {
+                Ok(value as u8)
+            }
+        };
+        let nibble = |value: i64, column: usize| {
+            if !(0..=0xF).contains(&value) {
+                Err(location.error(column, format!("value {} does not fit in a nibble", value)))
+            } else {
+                Ok(value as u8)
+            }
+        };
+
+        use self::Operand::*;
+
+        let instruction = match (mnemonic, operands.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n, c)]) => Instruction::ScrollDown(nibble(*n, *c)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("SYS", [Value(a, c)]) => Instruction::Sys(address(*a, *c)?),
+            ("JP", [Value(a, c)]) => Instruction::Jp(address(*a, *c)?),
+            ("JP", [Register(0), Value(a, c)]) => Instruction::JpV0(address(*a, *c)?),
+            ("CALL", [Value(a, c)]) => Instruction::Call(address(*a, *c)?),
+            ("SE", [Register(x), Register(y)]) => Instruction::SeReg { x: *x, y: *y },
+            ("SE", [Register(x), Value(v, c)]) => Instruction::SeImm {
+                x: *x,
+                nn: byte(*v, *c)?,
+            },
+            ("SNE", [Register(x), Register(y)]) => Instruction::SneReg { x: *x, y: *y },
+            ("SNE", [Register(x), Value(v, c)]) => Instruction::SneImm {
+                x: *x,
+                nn: byte(*v, *c)?,
+            },
+            ("LD", [Register(x), Register(y)]) => Instruction::LdReg { x: *x, y: *y },
+            ("LD", [Register(x), Value(v, c)]) => Instruction::LdImm {
+                x: *x,
+                nn: byte(*v, *c)?,
+            },
+            ("LD", [I, Value(a, c)]) => Instruction::LdI(address(*a, *c)?),
+            ("LD", [I, Long(a, c)]) => Instruction::LdILong(long_address(*a, *c)?),
+            ("SAVE", [Register(x), Register(y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [Register(x), Register(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("PLANE", [Value(n, c)]) => Instruction::Plane(planes(*n, *c)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [Register(x)]) => Instruction::Pitch(*x),
+            ("LD", [Register(x), Dt]) => Instruction::LdVxDt(*x),
+            ("LD", [Register(x), K]) => Instruction::LdVxK(*x),
+            ("LD", [Dt, Register(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [St, Register(x)]) => Instruction::LdStVx(*x),
+            ("LD", [F, Register(x)]) => Instruction::LdFVx(*x),
+            ("LD", [Hf, Register(x)]) => Instruction::LdHfVx(*x),
+            ("LD", [R, Register(x)]) => Instruction::LdRVx(*x),
+            ("LD", [Register(x), R]) => Instruction::LdVxR(*x),
+            ("LD", [B, Register(x)]) => Instruction::LdBVx(*x),
+            ("LD", [IndirectI, Register(x)]) => Instruction::LdIVx(*x),
+            ("LD", [Register(x), IndirectI]) => Instruction::LdVxI(*x),
+            ("ADD", [Register(x), Register(y)]) => Instruction::AddReg { x: *x, y: *y },
+            ("ADD", [Register(x), Value(v, c)]) => Instruction::AddImm {
+                x: *x,
+                nn: byte(*v, *c)?,
+            },
+            ("ADD", [I, Register(x)]) => Instruction::AddIVx(*x),
+            ("OR", [Register(x), Register(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Instruction::Subn { x: *x, y: *y },
+            ("SHR", [Register(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("RND", [Register(x), Value(v, c)]) => Instruction::Rnd {
+                x: *x,
+                nn: byte(*v, *c)?,
+            },
+            ("DRW", [Register(x), Register(y), Value(n, c)]) => Instruction::Drw {
+                x: *x,
+                y: *y,
+                n: nibble(*n, *c)?,
+            },
+            ("SKP", [Register(x)]) => Instruction::Skp(*x),
+            ("SKNP", [Register(x)]) => Instruction::Sknp(*x),
+            _ if is_mnemonic(mnemonic) => {
+                return Err(location.error(
+                    statement.column,
+                    format!("invalid operands for {}", mnemonic),
+                ))
+            }
+            _ => {
+                return Err(
+                    location.error(statement.column, format!("unknown mnemonic '{}'", mnemonic))
+                )
+            }
+        };
+
+        Ok(instruction)
+    }
+}
+
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Value(i64, usize),
+    Long(i64, usize),
+}
+
+const MNEMONICS: [&str; 31] = [
+    "CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE",
+    "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW",
+    "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn is_mnemonic(word: &str) -> bool {
+    MNEMONICS.contains(&word)
+}
+
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    is_mnemonic(&upper)
+        || [
+            "I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "DB", "DW", "EQU", "INCLUDE",
+        ]
+        .contains(&upper.as_str())
+}
+
+// `LD I, LONG NNNN` assembles to XO-CHIP's four byte `F000 NNNN`.
+fn is_long(operands: &[Vec<Token>]) -> bool {
+    match operands {
+        [_, second] => match second.first() {
+            Some(Token {
+                kind: TokenKind::Ident(name),
+                ..
+            }) => name.eq_ignore_ascii_case("LONG"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn parse_register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => {
+            digit.to_digit(16).map(|d| d as u8)
+        }
+        _ => None,
+    }
+}
+
+fn split_operands(tokens: &[Token], location: &Location) -> Result<Vec<Vec<Token>>, AsmError> {
+    let mut operands = Vec::new();
+    if tokens.is_empty() {
+        return Ok(operands);
+    }
+
+    let mut current = Vec::new();
+    for token in tokens {
+        if token.kind == TokenKind::Comma {
+            if current.is_empty() {
+                return Err(location.error(token.column, "expected an operand before ','"));
+            }
+            operands.push(current.split_off(0));
+        } else {
+            current.push(token.clone());
+        }
+    }
+    if current.is_empty() {
+        let last = &tokens[tokens.len() - 1];
+        return Err(location.error(last.column, "expected an operand after ','"));
+    }
+    operands.push(current);
+
+    Ok(operands)
+}
+
+fn tokenize(text: &str, location: &Location) -> Result<Vec<Token>, AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        let kind = match c {
+            ';' => break,
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '"' => {
+                let start = i + 1;
+                let end = match chars[start..].iter().position(|&c| c == '"') {
+                    Some(offset) => start + offset,
+                    None => return Err(location.error(column, "unterminated string")),
+                };
+                i = end + 1;
+                tokens.push(Token {
+                    kind: TokenKind::Str(chars[start..end].iter().collect()),
+                    column,
+                });
+                continue;
+            }
+            '#' | '$' | '%' | '0'..='9' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let value = parse_number(&literal).ok_or_else(|| {
+                    location.error(column, format!("invalid number '{}'", literal))
+                })?;
+                tokens.push(Token {
+                    kind: TokenKind::Number(value),
+                    column,
+                });
+                continue;
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '.' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Ident(chars[start..i].iter().collect()),
+                    column,
+                });
+                continue;
+            }
+            _ => return Err(location.error(column, format!("unexpected character '{}'", c))),
+        };
+
+        tokens.push(Token { kind, column });
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(literal: &str) -> Option<i64> {
+    let literal = literal.replace('_', "");
+    let lower = literal.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if let Some(hex) = lower.strip_prefix(|c| c == '#' || c == '$') {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix('%') {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let assembly =
+            assemble("start:\n    JP end\n    LD V0, 5 ; skipped\nend: JP start\n").unwrap();
+
+        assert_eq!(assembly.rom, [0x12, 0x04, 0x60, 0x05, 0x12, 0x00]);
+        assert_eq!(assembly.symbols["start"], 0x200);
+        assert_eq!(assembly.symbols["end"], 0x204);
+        let lines: Vec<(u16, usize)> = assembly
+            .lines
+            .iter()
+            .map(|info| (info.address, info.line))
+            .collect();
+        assert_eq!(lines, [(0x200, 2), (0x202, 3), (0x204, 4)]);
+    }
+
+    #[test]
+    fn equ_and_data() {
+        let source = "COUNT equ 3 + 2\nBASE equ $300\n\
+                      ld v1, COUNT\nLD I, BASE + 2 - -1\n\
+                      data: db 1, 0xFF, -1, \"AB\"\ndw #1234, data\n";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(
+            assembly.rom,
+            [0x61, 0x05, 0xA3, 0x03, 0x01, 0xFF, 0xFF, 0x41, 0x42, 0x12, 0x34, 0x02, 0x04]
+        );
+        assert_eq!(assembly.symbols["COUNT"], 5);
+        assert_eq!(assembly.symbols["data"], 0x204);
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let directory = env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.s");
+        let sub = directory.join("sub.s");
+        fs::write(&main, "CALL routine\nJP 0x202\ninclude \"sub.s\"\n").unwrap();
+        fs::write(&sub, "routine:\n  RET\n").unwrap();
+
+        let assembly = assemble_file(&main);
+        fs::remove_dir_all(&directory).unwrap();
+        let assembly = assembly.unwrap();
+
+        assert_eq!(assembly.rom, [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]);
+        let last = &assembly.lines[2];
+        assert_eq!((last.address, last.line), (0x204, 2));
+        assert_eq!(last.file, sub.display().to_string());
+
+        let err = assemble("  include \"missing.s\"").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 3));
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        assert_eq!(
+            error("JP nowhere"),
+            (1, 4, "undefined symbol 'nowhere'".to_string())
+        );
+        assert_eq!(
+            error("CLS\n  LD V0, 256"),
+            (2, 10, "value 256 does not fit in a byte".to_string())
+        );
+        assert_eq!(
+            error("  FOO V0"),
+            (1, 3, "unknown mnemonic 'FOO'".to_string())
+        );
+        assert_eq!(
+            error("ADD I, 1"),
+            (1, 1, "invalid operands for ADD".to_string())
+        );
+        assert_eq!(
+            error("a:\na: CLS"),
+            (2, 1, "'a' is already defined".to_string())
+        );
+        assert_eq!(
+            error("v1: CLS"),
+            (1, 1, "'v1' is a reserved name".to_string())
+        );
+        assert_eq!(
+            error("db 1 +"),
+            (1, 6, "expression ends with an operator".to_string())
+        );
+        assert_eq!(error("db \"AB"), (1, 4, "unterminated string".to_string()));
+        assert_eq!(
+            error("db 1 ? 2"),
+            (1, 6, "unexpected character '?'".to_string())
+        );
+    }
+
+    #[test]
+    fn overflowing_expressions_are_errors() {
+        assert_eq!(
+            error("BIG equ 0x7FFFFFFFFFFFFFFF\ndb BIG + 1"),
+            (2, 10, "value is too large".to_string())
+        );
+        assert_eq!(
+            error("BIG equ 0x7FFFFFFFFFFFFFFF\nSMALL equ -BIG\nLD I, SMALL - BIG"),
+            (3, 15, "value is too large".to_string())
+        );
+    }
+}
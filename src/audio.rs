@@ -0,0 +1,247 @@
+use crate::chip8::TIMER_HZ;
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub const NAMES: [&'static str; 4] = ["square", "sine", "triangle", "sawtooth"];
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+
+    /// The value of the waveform at `phase`, which runs from 0 to 1 over one period.
+    fn sample(self, phase: f64) -> f64 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f64,
+    pub waveform: Waveform,
+    /// From 0.0 (silent) to 1.0 (full scale).
+    pub volume: f64,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+impl Tone {
+    /// Generate `count` 16-bit samples, continuing from and updating `phase`.
+    pub fn samples(&self, phase: &mut f64, count: usize) -> Vec<i16> {
+        let step = self.frequency / SAMPLE_RATE as f64;
+        let amplitude = self.volume.clamp(0.0, 1.0) * i16::MAX as f64;
+
+        (0..count)
+            .map(|_| {
+                let sample = self.waveform.sample(*phase) * amplitude;
+                *phase = (*phase + step).fract();
+                sample as i16
+            })
+            .collect()
+    }
+
+    /// A WAV file holding a whole number of periods of the tone, so it can be looped seamlessly.
+    pub fn looped_wav(&self) -> Vec<u8> {
+        let periods = (self.frequency / 2.0).round().max(1.0);
+        let count = (periods * SAMPLE_RATE as f64 / self.frequency).round() as usize;
+
+        // Stretch the tone very slightly so the loop ends exactly on a period boundary.
+        let tone = Tone {
+            frequency: periods * SAMPLE_RATE as f64 / count as f64,
+            ..*self
+        };
+        let samples = tone.samples(&mut 0.0, count);
+
+        let mut wav = Vec::new();
+        write_wav(&mut wav, &samples).expect("writing to a Vec cannot fail");
+        wav
+    }
+}
+
+/// Somewhere to play the tone while the sound timer is active.
+pub trait AudioBackend {
+    /// Called once per 60 Hz frame with whether the tone should be sounding.
+    fn frame(&mut self, tone_on: bool);
+
+    /// Called when emulation ends, to flush anything still buffered.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Discards all audio.
+#[derive(Debug, Default)]
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn frame(&mut self, _tone_on: bool) {}
+}
+
+/// Renders the tone, and the silence around it, into a WAV file.
+///
+/// Every change between tone and silence is also recorded by frame number so tests can check
+/// exactly when the tone started and stopped.
+pub struct WavAudio {
+    path: PathBuf,
+    tone: Tone,
+    phase: f64,
+    samples: Vec<i16>,
+    frame: u64,
+    tone_on: bool,
+    transitions: Vec<(u64, bool)>,
+}
+
+impl WavAudio {
+    pub fn new<P: AsRef<Path>>(path: P, tone: Tone) -> WavAudio {
+        WavAudio {
+            path: path.as_ref().to_path_buf(),
+            tone,
+            phase: 0.0,
+            samples: Vec::new(),
+            frame: 0,
+            tone_on: false,
+            transitions: Vec::new(),
+        }
+    }
+
+    /// Each frame the tone turned on (`true`) or off (`false`).
+    pub fn transitions(&self) -> &[(u64, bool)] {
+        &self.transitions
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+}
+
+impl AudioBackend for WavAudio {
+    fn frame(&mut self, tone_on: bool) {
+        if tone_on != self.tone_on {
+            self.transitions.push((self.frame, tone_on));
+            self.tone_on = tone_on;
+        }
+
+        // Spread the remainder so exactly SAMPLE_RATE samples are written every 60 frames.
+        let start = self.frame * SAMPLE_RATE as u64 / TIMER_HZ as u64;
+        let end = (self.frame + 1) * SAMPLE_RATE as u64 / TIMER_HZ as u64;
+        let count = (end - start) as usize;
+
+        if tone_on {
+            let samples = self.tone.samples(&mut self.phase, count);
+            self.samples.extend(samples);
+        } else {
+            self.phase = 0.0;
+            self.samples.resize(self.samples.len() + count, 0);
+        }
+
+        self.frame += 1;
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        write_wav(&mut file, &self.samples)?;
+        file.flush()
+    }
+}
+
+/// Write 16-bit mono PCM samples as a WAV file.
+pub fn write_wav<W: Write>(writer: &mut W, samples: &[i16]) -> io::Result<()> {
+    let data_size = samples.len() as u32 * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // Chunk size
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // Mono
+    writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+    writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8State;
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn wav_follows_the_sound_timer() {
+        // LD V0, 10; LD ST, V0; JP 0x204
+        let mut chip8 = Chip8State::with_rom(&[0x60, 0x0A, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let path = env::temp_dir().join(format!("chip8-audio-{}.wav", std::process::id()));
+        let mut audio = WavAudio::new(&path, Tone::default());
+
+        for _ in 0..120 {
+            chip8.run_frame().unwrap();
+            audio.frame(chip8.sound_active());
+        }
+        audio.finish().unwrap();
+
+        assert_eq!(audio.transitions(), [(0, true), (9, false)]);
+
+        let samples = audio.samples();
+        let per_frame = SAMPLE_RATE as usize / TIMER_HZ as usize;
+        assert_eq!(samples.len(), 2 * SAMPLE_RATE as usize);
+        assert!(samples[..9 * per_frame].iter().any(|&sample| sample != 0));
+        assert!(samples[9 * per_frame..].iter().all(|&sample| sample == 0));
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+        assert_eq!(&wav[40..44], &(samples.len() as u32 * 2).to_le_bytes());
+    }
+}
@@ -0,0 +1,1394 @@
+use crate::error::Chip8Error;
+use crate::instruction::{Instruction, LONG_PREFIX};
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, SeededRandom};
+use crate::trace::Tracer;
+
+use std::fs::File;
+use std::io::prelude::*;
+
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+
+pub const FONT_START: usize = 0x000;
+pub const BIG_FONT_START: usize = 0x050;
+
+pub const MEMORY_SIZE: usize = 0x10000;
+pub const PROGRAM_START: usize = 512;
+
+/// Number of XO-CHIP bitplanes. Each pixel in `video_buf` holds one bit per plane.
+pub const PLANES: usize = 2;
+/// RGB colours for each pixel value, indexed by the bitplanes that are set.
+pub const PALETTE: [[u8; 3]; 1 << PLANES] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+];
+
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+
+pub const TIMER_HZ: u32 = 60;
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 700;
+// The timers tick at most once per instruction, so slower clocks can't keep them at TIMER_HZ.
+pub const MIN_INSTRUCTIONS_PER_SECOND: u32 = TIMER_HZ;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+pub struct Chip8State {
+    pub memory: Vec<u8>,
+    pub pc: u16,
+    pub registers: [u8; 16],
+    pub i_register: u16,
+    pub stack: [u16; 12],
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keys: [bool; 16],
+    pub video_buf: [[u8; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT],
+    pub hires: bool,
+    pub planes: u8,
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+    pub halted: bool,
+    pub rpl_flags: [u8; 16],
+    pub instructions_per_second: u32,
+    pub cycles: u64,
+    pub quirks: Quirks,
+    /// `rom_hash` of the loaded ROM, used to check save states belong to it.
+    pub rom_hash: u64,
+    /// Where to trace each instruction before it executes, if anywhere.
+    pub tracer: Option<Tracer>,
+    /// Whether to record the data reads and writes each step makes in `accesses`.
+    pub record_accesses: bool,
+    /// Addresses read or written by the last step, not counting instruction fetches.
+    pub accesses: Vec<(usize, MemoryAccess)>,
+    pub(crate) timer_phase: u32,
+    pub(crate) vblank: bool,
+    /// Where `CXNN` gets its numbers, a generator with a random seed by default.
+    pub random: Box<dyn RandomSource>,
+}
+
+impl Chip8State {
+    pub fn new() -> Chip8State {
+        let mut s = Chip8State {
+            memory: vec![0; MEMORY_SIZE],
+            pc: 512,
+            registers: [0; 16],
+            i_register: 0,
+            stack: [0; 12],
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            keys: [false; 16],
+            video_buf: [[0; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT],
+            hires: false,
+            planes: 1,
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            halted: false,
+            rpl_flags: [0; 16],
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            cycles: 0,
+            quirks: Quirks::default(),
+            rom_hash: rom_hash(&[]),
+            tracer: None,
+            record_accesses: false,
+            accesses: Vec::new(),
+            timer_phase: 0,
+            vblank: false,
+            random: Box::new(SeededRandom::from_entropy()),
+        };
+
+        s.load_sprites();
+
+        s
+    }
+
+    /// A new machine with `rom` loaded from memory rather than a file.
+    pub fn with_rom(rom: &[u8]) -> Result<Chip8State, Chip8Error> {
+        let mut chip8 = Chip8State::new();
+        chip8.load_rom_bytes(rom)?;
+
+        Ok(chip8)
+    }
+
+    fn load_sprites(&mut self) {
+        let sprites = [
+            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+            0x20, 0x60, 0x20, 0x20, 0x70, // 1
+            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+        ];
+
+        self.memory[FONT_START..FONT_START + sprites.len()].copy_from_slice(&sprites);
+
+        let big_sprites = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+
+        self.memory[BIG_FONT_START..BIG_FONT_START + big_sprites.len()]
+            .copy_from_slice(&big_sprites);
+    }
+
+    pub fn screen_width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub fn screen_height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    fn clear_planes(&mut self, planes: u8) {
+        for row in self.video_buf.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !planes;
+            }
+        }
+    }
+
+    // Only the selected bitplanes move; the others stay where they are.
+    fn scroll(&mut self, right: isize, down: isize) {
+        let width = self.screen_width() as isize;
+        let height = self.screen_height() as isize;
+        let previous = self.video_buf;
+
+        for row in 0..height {
+            for col in 0..width {
+                let (src_row, src_col) = (row - down, col - right);
+                let moved = if src_row >= 0 && src_row < height && src_col >= 0 && src_col < width {
+                    previous[src_row as usize][src_col as usize] & self.planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.video_buf[row as usize][col as usize];
+                *pixel = (*pixel & !self.planes) | moved;
+            }
+        }
+    }
+
+    pub fn load_rom(&mut self, path: &str) -> Result<(), Chip8Error> {
+        let mut file = File::open(path)?;
+        let mut rom = Vec::new();
+
+        file.read_to_end(&mut rom)?;
+
+        self.load_rom_bytes(&rom)
+    }
+
+    /// Make `CXNN` produce the same sequence of numbers on every run with the same seed.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Box::new(SeededRandom::new(seed));
+    }
+
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.memory.len() - PROGRAM_START;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+
+        self.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        self.rom_hash = rom_hash(rom);
+
+        Ok(())
+    }
+
+    fn read_memory(&self, address: usize) -> Result<u8, Chip8Error> {
+        match self.memory.get(address) {
+            Some(value) => Ok(*value),
+            None => Err(Chip8Error::MemoryOutOfRange { address }),
+        }
+    }
+
+    // Reads made by an instruction, as opposed to fetching one.
+    fn read_data(&mut self, address: usize) -> Result<u8, Chip8Error> {
+        if self.record_accesses {
+            self.accesses.push((address, MemoryAccess::Read));
+        }
+
+        self.read_memory(address)
+    }
+
+    fn write_memory(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        if self.record_accesses {
+            self.accesses.push((address, MemoryAccess::Write));
+        }
+
+        match self.memory.get_mut(address) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(Chip8Error::MemoryOutOfRange { address }),
+        }
+    }
+
+    pub fn get_opcode(&self) -> Result<u16, Chip8Error> {
+        let address = self.pc as usize;
+
+        Ok((self.read_memory(address)? as u16) << 8 | (self.read_memory(address + 1)? as u16))
+    }
+
+    /// Decode the instruction at `address` without executing it.
+    pub fn instruction_at(&self, address: u16) -> Option<Instruction> {
+        let address = address as usize;
+        let word = |address: usize| -> Option<u16> {
+            Some((*self.memory.get(address)? as u16) << 8 | *self.memory.get(address + 1)? as u16)
+        };
+
+        let opcode = word(address)?;
+        if opcode == LONG_PREFIX {
+            Instruction::decode_pair(opcode, word(address + 2)?)
+        } else {
+            Instruction::decode(opcode)
+        }
+    }
+
+    pub fn process_opcode(&mut self, opcode: u16) -> Result<(), Chip8Error> {
+        let next = if opcode == LONG_PREFIX {
+            let address = self.pc as usize + 2;
+            (self.read_memory(address)? as u16) << 8 | self.read_memory(address + 1)? as u16
+        } else {
+            0
+        };
+        let instruction = Instruction::decode_pair(opcode, next);
+
+        if let Some(mut tracer) = self.tracer.take() {
+            let traced = tracer.record(self, opcode, instruction);
+            self.tracer = Some(tracer);
+            traced.map_err(Chip8Error::TraceUnwritable)?;
+        }
+
+        match instruction {
+            Some(instruction) => self.execute(instruction),
+            None => Err(Chip8Error::UnknownOpcode {
+                opcode,
+                address: self.pc,
+            }),
+        }
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        match instruction {
+            Instruction::Cls => self.clear_planes(self.planes),
+            Instruction::ScrollDown(n) => self.scroll(0, n as isize),
+            Instruction::ScrollRight => self.scroll(4, 0),
+            Instruction::ScrollLeft => self.scroll(-4, 0),
+            Instruction::Exit => {
+                self.halted = true;
+                return Ok(());
+            }
+            Instruction::Low => {
+                self.hires = false;
+                self.clear_planes(0xFF);
+            }
+            Instruction::High => {
+                self.hires = true;
+                self.clear_planes(0xFF);
+            }
+            Instruction::Ret => {
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow { address: self.pc });
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
+                return Ok(());
+            }
+            Instruction::Sys(_) => {
+                return Err(Chip8Error::UnknownOpcode {
+                    opcode: instruction.encode(),
+                    address: self.pc,
+                });
+            }
+            Instruction::Jp(address) => {
+                self.pc = address;
+                return Ok(());
+            }
+            Instruction::Call(address) => {
+                if self.sp as usize >= self.stack.len() {
+                    return Err(Chip8Error::StackOverflow { address: self.pc });
+                }
+                self.stack[self.sp as usize] = self.pc.wrapping_add(2);
+                self.sp += 1;
+                self.pc = address;
+                return Ok(());
+            }
+            Instruction::SeImm { x, nn } => {
+                if self.registers[x as usize] == nn {
+                    self.skip()?;
+                }
+            }
+            Instruction::SneImm { x, nn } => {
+                if self.registers[x as usize] != nn {
+                    self.skip()?;
+                }
+            }
+            Instruction::SeReg { x, y } => {
+                if self.registers[x as usize] == self.registers[y as usize] {
+                    self.skip()?;
+                }
+            }
+            Instruction::SaveRange { x, y } => {
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let address = self.i_register as usize + offset;
+                    self.write_memory(address, self.registers[register])?;
+                }
+            }
+            Instruction::LoadRange { x, y } => {
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let address = self.i_register as usize + offset;
+                    self.registers[register] = self.read_data(address)?;
+                }
+            }
+            Instruction::LdImm { x, nn } => {
+                self.registers[x as usize] = nn;
+            }
+            Instruction::AddImm { x, nn } => {
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(nn);
+            }
+            Instruction::LdReg { x, y } => {
+                self.registers[x as usize] = self.registers[y as usize];
+            }
+            Instruction::Or { x, y } => {
+                self.registers[x as usize] |= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            }
+            Instruction::And { x, y } => {
+                self.registers[x as usize] &= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            }
+            Instruction::Xor { x, y } => {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
+                }
+            }
+            Instruction::AddReg { x, y } => {
+                // VF is set last, so it holds the flag even when it's also VX or VY.
+                let (x, y) = (x as usize, y as usize);
+                let (sum, carry) = self.registers[x].overflowing_add(self.registers[y]);
+                self.registers[x] = sum;
+                self.registers[0xF] = carry as u8;
+            }
+            Instruction::Sub { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                let (difference, borrow) = self.registers[x].overflowing_sub(self.registers[y]);
+                self.registers[x] = difference;
+                self.registers[0xF] = !borrow as u8;
+            }
+            Instruction::Shr { x, y } => {
+                let source = self.shift_source(x, y);
+                self.registers[x as usize] = source >> 1;
+                self.registers[0xF] = source & 0x1;
+            }
+            Instruction::Subn { x, y } => {
+                let (x, y) = (x as usize, y as usize);
+                let (difference, borrow) = self.registers[y].overflowing_sub(self.registers[x]);
+                self.registers[x] = difference;
+                self.registers[0xF] = !borrow as u8;
+            }
+            Instruction::Shl { x, y } => {
+                let source = self.shift_source(x, y);
+                self.registers[x as usize] = source << 1;
+                self.registers[0xF] = source >> 7;
+            }
+            Instruction::SneReg { x, y } => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.skip()?;
+                }
+            }
+            Instruction::LdI(address) | Instruction::LdILong(address) => {
+                self.i_register = address;
+            }
+            Instruction::JpV0(address) => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.registers[(address >> 8) as usize]
+                } else {
+                    self.registers[0]
+                };
+                self.pc = address + offset as u16;
+                return Ok(());
+            }
+            Instruction::Rnd { x, nn } => {
+                let random = self.random.next_byte(&self.memory);
+                self.registers[x as usize] = random & nn;
+            }
+            Instruction::Drw { x, y, n } => {
+                if self.quirks.wait_for_vblank {
+                    if !self.vblank {
+                        // Stall on this instruction until the next timer tick.
+                        return Ok(());
+                    }
+                    self.vblank = false;
+                }
+
+                let width = self.screen_width();
+                let height = self.screen_height();
+                let start_row = self.registers[y as usize] as usize % height;
+                let start_col = self.registers[x as usize] as usize % width;
+
+                // DXY0 draws a 16x16 sprite made of two bytes per row.
+                let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n as usize, 1) };
+                let sprite_width = bytes_per_row * 8;
+
+                // With both XO-CHIP bitplanes selected, the second plane's sprite data
+                // immediately follows the first's.
+                let mut address = self.i_register as usize;
+
+                self.registers[0xF] = 0;
+                for plane in (0..PLANES).map(|plane| 1u8 << plane) {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
+
+                    for sprite_row in 0..rows {
+                        let mut sprite_bits = 0u16;
+                        for _ in 0..bytes_per_row {
+                            sprite_bits = sprite_bits << 8 | self.read_data(address)? as u16;
+                            address += 1;
+                        }
+
+                        for sprite_col in 0..sprite_width {
+                            let mut screen_row = start_row + sprite_row;
+                            let mut screen_col = start_col + sprite_col;
+                            let sprite_pixel =
+                                (sprite_bits >> (sprite_width - 1 - sprite_col)) & 0x1;
+
+                            if screen_row >= height || screen_col >= width {
+                                if self.quirks.clip_sprites {
+                                    continue;
+                                }
+                                screen_row %= height;
+                                screen_col %= width;
+                            }
+
+                            if sprite_pixel == 1 {
+                                if self.video_buf[screen_row][screen_col] & plane != 0 {
+                                    self.registers[0xF] = 1;
+                                }
+                                self.video_buf[screen_row][screen_col] ^= plane;
+                            }
+                        }
+                    }
+                }
+            }
+            Instruction::Skp(x) => {
+                if self.keys[(self.registers[x as usize] & 0xF) as usize] {
+                    self.skip()?;
+                }
+            }
+            Instruction::Sknp(x) => {
+                if !self.keys[(self.registers[x as usize] & 0xF) as usize] {
+                    self.skip()?;
+                }
+            }
+            Instruction::Plane(n) => {
+                self.planes = n & ((1 << PLANES) - 1) as u8;
+            }
+            Instruction::Audio => {
+                for i in 0..AUDIO_PATTERN_SIZE {
+                    self.audio_pattern[i] = self.read_data(self.i_register as usize + i)?;
+                }
+            }
+            Instruction::LdVxDt(x) => {
+                self.registers[x as usize] = self.delay_timer;
+            }
+            Instruction::LdVxK(x) => match self.keys.iter().position(|&pressed| pressed) {
+                Some(key) => self.registers[x as usize] = key as u8,
+                None => return Ok(()),
+            },
+            Instruction::LdDtVx(x) => {
+                self.delay_timer = self.registers[x as usize];
+            }
+            Instruction::LdStVx(x) => {
+                self.sound_timer = self.registers[x as usize];
+            }
+            Instruction::AddIVx(x) => {
+                self.i_register = self
+                    .i_register
+                    .wrapping_add(self.registers[x as usize] as u16);
+            }
+            Instruction::Pitch(x) => {
+                self.pitch = self.registers[x as usize];
+            }
+            Instruction::LdFVx(x) => {
+                self.i_register =
+                    (FONT_START + (self.registers[x as usize] & 0xF) as usize * 5) as u16;
+            }
+            Instruction::LdHfVx(x) => {
+                self.i_register =
+                    (BIG_FONT_START + (self.registers[x as usize] & 0xF) as usize * 10) as u16;
+            }
+            Instruction::LdBVx(x) => {
+                let value = self.registers[x as usize];
+                let i = self.i_register as usize;
+                self.write_memory(i, (value / 100) % 10)?; // Hundreds
+                self.write_memory(i + 1, (value / 10) % 10)?; // Tens
+                self.write_memory(i + 2, value % 10)?; // Ones
+            }
+            Instruction::LdIVx(x) => {
+                for i in 0..=x as usize {
+                    self.write_memory(self.i_register as usize + i, self.registers[i])?;
+                }
+                let increment = self.quirks.load_store_increments_i.amount(x);
+                self.i_register = self.i_register.wrapping_add(increment);
+            }
+            Instruction::LdVxI(x) => {
+                for i in 0..=x as usize {
+                    self.registers[i] = self.read_data(self.i_register as usize + i)?;
+                }
+                let increment = self.quirks.load_store_increments_i.amount(x);
+                self.i_register = self.i_register.wrapping_add(increment);
+            }
+            Instruction::LdRVx(x) => {
+                let count = x as usize + 1;
+                self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+            }
+            Instruction::LdVxR(x) => {
+                let count = x as usize + 1;
+                self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+            }
+        }
+
+        self.pc = self.pc.wrapping_add(instruction.size());
+
+        Ok(())
+    }
+
+    // Skip the next instruction, which is four bytes long if it's XO-CHIP's `F000 NNNN`.
+    fn skip(&mut self) -> Result<(), Chip8Error> {
+        let next = self.pc as usize + 2;
+        let opcode = (self.read_memory(next)? as u16) << 8 | self.read_memory(next + 1)? as u16;
+
+        self.pc = self
+            .pc
+            .wrapping_add(if opcode == LONG_PREFIX { 4 } else { 2 });
+
+        Ok(())
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[y as usize]
+        } else {
+            self.registers[x as usize]
+        }
+    }
+
+    /// Fetch and execute a single instruction, then advance emulated time by one cycle.
+    ///
+    /// Once the program has exited with `00FD` only emulated time advances.
+    ///
+    /// Returns `true` if the delay and sound timers ticked during the cycle.
+    pub fn step(&mut self) -> Result<bool, Chip8Error> {
+        self.accesses.clear();
+
+        if !self.halted {
+            let opcode = self.get_opcode()?;
+            self.process_opcode(opcode)?;
+        }
+
+        Ok(self.advance_clock())
+    }
+
+    // A cycle lasts 1/instructions_per_second seconds. Adding TIMER_HZ to the phase each cycle
+    // and ticking whenever it wraps past instructions_per_second gives exactly TIMER_HZ ticks
+    // per emulated second, whatever the clock rate.
+    fn advance_clock(&mut self) -> bool {
+        let instructions_per_second = self.instructions_per_second.max(1);
+
+        self.cycles += 1;
+        self.timer_phase += TIMER_HZ;
+
+        if self.timer_phase >= instructions_per_second {
+            self.timer_phase -= instructions_per_second;
+            self.timer_phase = self.timer_phase.min(instructions_per_second - 1);
+            self.tick_timers();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+        self.random.tick();
+
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// Run instructions until 1/60th of a second of emulated time has passed.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        while !self.step()? {}
+
+        Ok(())
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+}
+
+/// A 64-bit FNV-1a hash identifying a ROM.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+// Registers from VX to VY inclusive, counting down if X is greater than Y.
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+impl Default for Chip8State {
+    fn default() -> Chip8State {
+        Chip8State::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::quirks::LoadStoreIncrement;
+    use crate::random::ScriptedRandom;
+
+    // Quirks without the vblank wait, so every instruction finishes in a single step.
+    fn machine(program: &[u16]) -> Chip8State {
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut chip8 = Chip8State::with_rom(&rom).unwrap();
+        chip8.quirks = Quirks::MODERN;
+        chip8
+    }
+
+    fn run(chip8: &mut Chip8State, steps: usize) {
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+    }
+
+    fn lit(chip8: &Chip8State) -> usize {
+        chip8
+            .video_buf
+            .iter()
+            .flat_map(|row| row.iter())
+            .filter(|&&pixel| pixel != 0)
+            .count()
+    }
+
+    #[test]
+    fn with_rom_loads_at_program_start() {
+        let chip8 = Chip8State::with_rom(&[0x12, 0x34]).unwrap();
+        assert_eq!(
+            &chip8.memory[PROGRAM_START..PROGRAM_START + 2],
+            &[0x12, 0x34]
+        );
+        assert_eq!(chip8.pc as usize, PROGRAM_START);
+        assert_eq!(chip8.rom_hash, rom_hash(&[0x12, 0x34]));
+
+        let too_large = vec![0; MEMORY_SIZE];
+        assert!(matches!(
+            Chip8State::with_rom(&too_large),
+            Err(Chip8Error::RomTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn cls_clears_the_screen() {
+        let mut chip8 = machine(&[0x00E0]);
+        chip8.video_buf[3][4] = 1;
+        run(&mut chip8, 1);
+        assert_eq!(lit(&chip8), 0);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let mut chip8 = machine(&[0x2206, 0x0000, 0x0000, 0x00EE]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x206);
+        assert_eq!(chip8.sp, 1);
+        assert_eq!(chip8.stack[0], 0x202);
+
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.sp, 0);
+    }
+
+    #[test]
+    fn ret_with_empty_stack_underflows() {
+        let mut chip8 = machine(&[0x00EE]);
+        assert!(matches!(
+            chip8.step(),
+            Err(Chip8Error::StackUnderflow { address: 0x200 })
+        ));
+    }
+
+    #[test]
+    fn call_with_full_stack_overflows() {
+        let mut chip8 = machine(&[0x2200]);
+        run(&mut chip8, 12);
+        assert!(matches!(
+            chip8.step(),
+            Err(Chip8Error::StackOverflow { address: 0x200 })
+        ));
+    }
+
+    #[test]
+    fn sys_is_unsupported() {
+        let mut chip8 = machine(&[0x0123]);
+        assert!(matches!(
+            chip8.step(),
+            Err(Chip8Error::UnknownOpcode {
+                opcode: 0x0123,
+                address: 0x200
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut chip8 = machine(&[0x5121]);
+        assert!(matches!(
+            chip8.step(),
+            Err(Chip8Error::UnknownOpcode {
+                opcode: 0x5121,
+                address: 0x200
+            })
+        ));
+    }
+
+    #[test]
+    fn scroll() {
+        let mut chip8 = machine(&[0x00C2, 0x00FB, 0x00FC, 0x00FC]);
+        chip8.video_buf[0][8] = 1;
+
+        run(&mut chip8, 1);
+        assert_eq!(chip8.video_buf[2][8], 1);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.video_buf[2][12], 1);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.video_buf[2][8], 1);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.video_buf[2][4], 1);
+        assert_eq!(lit(&chip8), 1);
+    }
+
+    #[test]
+    fn exit_halts() {
+        let mut chip8 = machine(&[0x00FD]);
+        run(&mut chip8, 2);
+        assert!(chip8.halted);
+        assert_eq!(chip8.pc, 0x200);
+    }
+
+    #[test]
+    fn resolution_switches_clear_the_screen() {
+        let mut chip8 = machine(&[0x00FF, 0x00FE]);
+        chip8.video_buf[0][0] = 1;
+        run(&mut chip8, 1);
+        assert!(chip8.hires);
+        assert_eq!(chip8.screen_width(), HIRES_SCREEN_WIDTH);
+        assert_eq!(lit(&chip8), 0);
+
+        chip8.video_buf[0][0] = 1;
+        run(&mut chip8, 1);
+        assert!(!chip8.hires);
+        assert_eq!(chip8.screen_width(), SCREEN_WIDTH);
+        assert_eq!(lit(&chip8), 0);
+    }
+
+    #[test]
+    fn jump() {
+        let mut chip8 = machine(&[0x1234]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x234);
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        let mut chip8 = machine(&[0xB300]);
+        chip8.registers[0] = 0x10;
+        chip8.registers[3] = 0x20;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x310);
+
+        let mut chip8 = machine(&[0xB300]);
+        chip8.quirks.jump_uses_vx = true;
+        chip8.registers[0] = 0x10;
+        chip8.registers[3] = 0x20;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x320);
+    }
+
+    #[test]
+    fn skips() {
+        let cases = [
+            (0x3105, true),
+            (0x3106, false),
+            (0x4105, false),
+            (0x4106, true),
+            (0x5120, true),
+            (0x5130, false),
+            (0x9120, false),
+            (0x9130, true),
+        ];
+
+        for &(opcode, skips) in cases.iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.registers[1] = 5;
+            chip8.registers[2] = 5;
+            chip8.registers[3] = 6;
+            run(&mut chip8, 1);
+            let expected = if skips { 0x204 } else { 0x202 };
+            assert_eq!(chip8.pc, expected, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn skip_steps_over_long_load() {
+        let mut chip8 = machine(&[0x3000, 0xF000, 0x1234, 0x00E0]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn key_skips() {
+        for &(opcode, pressed, skips) in [
+            (0xE19E, true, true),
+            (0xE19E, false, false),
+            (0xE1A1, true, false),
+            (0xE1A1, false, true),
+        ]
+        .iter()
+        {
+            let mut chip8 = machine(&[opcode]);
+            chip8.registers[1] = 0xA;
+            chip8.keys[0xA] = pressed;
+            run(&mut chip8, 1);
+            let expected = if skips { 0x204 } else { 0x202 };
+            assert_eq!(chip8.pc, expected, "{:04X} pressed {}", opcode, pressed);
+        }
+    }
+
+    #[test]
+    fn load_and_add_immediate() {
+        let mut chip8 = machine(&[0x6A42, 0x7A01]);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers[0xA], 0x43);
+    }
+
+    #[test]
+    fn add_immediate_wraps_without_touching_vf() {
+        let mut chip8 = machine(&[0x7102]);
+        chip8.registers[1] = 0xFF;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[1], 0x01);
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn register_ops() {
+        let cases = [
+            (0x8120, 0x0F),
+            (0x8121, 0x3F),
+            (0x8122, 0x0C),
+            (0x8123, 0x33),
+        ];
+
+        for &(opcode, expected) in cases.iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.registers[1] = 0x3C;
+            chip8.registers[2] = 0x0F;
+            chip8.registers[0xF] = 7;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.registers[1], expected, "{:04X}", opcode);
+            assert_eq!(chip8.registers[0xF], 7, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk() {
+        for &opcode in [0x8121, 0x8122, 0x8123].iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.quirks.logic_resets_vf = true;
+            chip8.registers[0xF] = 7;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.registers[0xF], 0, "{:04X}", opcode);
+        }
+    }
+
+    // (opcode, VX, VY, result, VF)
+    fn check_arithmetic(cases: &[(u16, u8, u8, u8, u8)]) {
+        for &(opcode, vx, vy, result, flag) in cases {
+            let mut chip8 = machine(&[opcode]);
+            chip8.registers[1] = vx;
+            chip8.registers[2] = vy;
+            run(&mut chip8, 1);
+            assert_eq!(
+                chip8.registers[1], result,
+                "{:04X} with {:#04X}, {:#04X}",
+                opcode, vx, vy
+            );
+            assert_eq!(
+                chip8.registers[0xF], flag,
+                "VF for {:04X} with {:#04X}, {:#04X}",
+                opcode, vx, vy
+            );
+        }
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        check_arithmetic(&[
+            (0x8124, 0x10, 0x20, 0x30, 0),
+            (0x8124, 0xFF, 0x01, 0x00, 1),
+            (0x8124, 0xF0, 0x20, 0x10, 1),
+        ]);
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        check_arithmetic(&[
+            (0x8125, 0x30, 0x10, 0x20, 1),
+            (0x8125, 0x10, 0x10, 0x00, 1),
+            (0x8125, 0x10, 0x30, 0xE0, 0),
+        ]);
+    }
+
+    #[test]
+    fn subn_sets_not_borrow() {
+        check_arithmetic(&[
+            (0x8127, 0x10, 0x30, 0x20, 1),
+            (0x8127, 0x10, 0x10, 0x00, 1),
+            (0x8127, 0x30, 0x10, 0xE0, 0),
+        ]);
+    }
+
+    #[test]
+    fn shifts_set_shifted_out_bit() {
+        // The test quirks shift VY into VX.
+        check_arithmetic(&[
+            (0x8126, 0xFF, 0x05, 0x02, 1),
+            (0x8126, 0xFF, 0x04, 0x02, 0),
+            (0x812E, 0xFF, 0x81, 0x02, 1),
+            (0x812E, 0xFF, 0x41, 0x82, 0),
+        ]);
+    }
+
+    #[test]
+    fn shifts_in_place_quirk() {
+        let mut chip8 = machine(&[0x8126, 0x834E]);
+        chip8.quirks.shift_uses_vy = false;
+        chip8.registers[1] = 0x03;
+        chip8.registers[3] = 0x80;
+        chip8.registers[4] = 0xFF;
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers[1], 0x01);
+        assert_eq!(chip8.registers[3], 0x00);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        // The result is written first, then replaced by the flag.
+        let cases = [
+            (0x8F24, 0xFF, 0x01, 1),
+            (0x8F25, 0x01, 0x02, 0),
+            (0x8F27, 0x01, 0x02, 1),
+            (0x8F26, 0x03, 0x00, 1),
+            (0x8F2E, 0x40, 0x00, 0),
+        ];
+
+        for &(opcode, vf, vy, flag) in cases.iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.quirks.shift_uses_vy = false;
+            chip8.registers[0xF] = vf;
+            chip8.registers[2] = vy;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.registers[0xF], flag, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn load_i() {
+        let mut chip8 = machine(&[0xA123, 0xF000, 0xBEEF]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register, 0x123);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register, 0xBEEF);
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn add_to_i_wraps() {
+        let mut chip8 = machine(&[0xF11E, 0xF11E]);
+        chip8.i_register = 0xFFF0;
+        chip8.registers[1] = 0x08;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register, 0xFFF8);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register, 0x0000);
+    }
+
+    #[test]
+    fn random_is_masked() {
+        let mut chip8 = machine(&[0xC10F, 0xC200]);
+        chip8.seed_random(1);
+        chip8.registers[2] = 0xAA;
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers[1] & 0xF0, 0);
+        assert_eq!(chip8.registers[2], 0);
+    }
+
+    #[test]
+    fn random_comes_from_the_source() {
+        let mut chip8 = machine(&[0xC1FF, 0xC2F0, 0xC3FF]);
+        chip8.random = Box::new(ScriptedRandom::new(vec![0xFF, 0x3C]));
+        run(&mut chip8, 3);
+        assert_eq!(chip8.registers[1..4], [0xFF, 0x30, 0xFF]);
+    }
+
+    #[test]
+    fn random_is_reproducible_with_a_seed() {
+        let program = [0xC1FF, 0xC2FF, 0xC3FF, 0xC4FF];
+        let mut first = machine(&program);
+        let mut second = machine(&program);
+        first.seed_random(42);
+        second.seed_random(42);
+        run(&mut first, 4);
+        run(&mut second, 4);
+        assert_eq!(first.registers, second.registers);
+    }
+
+    #[test]
+    fn draw_sets_vf_on_collision() {
+        // Draw the "0" glyph twice at (1, 2); the second draw erases it.
+        let mut chip8 = machine(&[0xF029, 0xD125, 0xD125]);
+        chip8.registers[1] = 1;
+        chip8.registers[2] = 2;
+
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers[0xF], 0);
+        assert_eq!(lit(&chip8), 14);
+        assert_eq!(chip8.video_buf[2][1..5], [1, 1, 1, 1]);
+        assert_eq!(chip8.video_buf[3][1..5], [1, 0, 0, 1]);
+
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 1);
+        assert_eq!(lit(&chip8), 0);
+    }
+
+    #[test]
+    fn draw_clears_vf_without_collision() {
+        let mut chip8 = machine(&[0xD011]);
+        chip8.i_register = 0x300;
+        chip8.memory[0x300] = 0x80;
+        chip8.registers[0xF] = 1;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 0);
+        assert_eq!(chip8.video_buf[0][0], 1);
+    }
+
+    #[test]
+    fn draw_wraps_or_clips_at_the_edges() {
+        let mut chip8 = machine(&[0xD011]);
+        chip8.i_register = 0x300;
+        chip8.memory[0x300] = 0xFF;
+        chip8.registers[0] = 60;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.video_buf[0][60..64], [1, 1, 1, 1]);
+        assert_eq!(chip8.video_buf[0][0..4], [1, 1, 1, 1]);
+
+        let mut chip8 = machine(&[0xD011]);
+        chip8.quirks.clip_sprites = true;
+        chip8.i_register = 0x300;
+        chip8.memory[0x300] = 0xFF;
+        chip8.registers[0] = 60;
+        run(&mut chip8, 1);
+        assert_eq!(lit(&chip8), 4);
+
+        // The starting position always wraps.
+        let mut chip8 = machine(&[0xD011]);
+        chip8.quirks.clip_sprites = true;
+        chip8.i_register = 0x300;
+        chip8.memory[0x300] = 0x80;
+        chip8.registers[0] = 65;
+        chip8.registers[1] = 33;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.video_buf[1][1], 1);
+    }
+
+    #[test]
+    fn draw_large_sprite_in_hires() {
+        let mut chip8 = machine(&[0x00FF, 0xD010]);
+        chip8.i_register = 0x300;
+        for byte in chip8.memory[0x300..0x320].iter_mut() {
+            *byte = 0xFF;
+        }
+        chip8.registers[0] = 100;
+        run(&mut chip8, 2);
+        assert_eq!(lit(&chip8), 256);
+        assert_eq!(chip8.video_buf[15][115], 1);
+    }
+
+    #[test]
+    fn draw_waits_for_vblank_quirk() {
+        let mut chip8 = machine(&[0xD015]);
+        chip8.quirks.wait_for_vblank = true;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x200);
+
+        chip8.tick_timers();
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x202);
+        assert!(lit(&chip8) > 0);
+    }
+
+    #[test]
+    fn plane_selects_bitplanes() {
+        let mut chip8 = machine(&[0xF201, 0xD011, 0xF301, 0x00E0]);
+        chip8.i_register = 0x300;
+        chip8.memory[0x300] = 0x80;
+        chip8.memory[0x301] = 0x80;
+        run(&mut chip8, 2);
+        assert_eq!(chip8.planes, 2);
+        assert_eq!(chip8.video_buf[0][0], 2);
+
+        run(&mut chip8, 2);
+        assert_eq!(chip8.planes, 3);
+        assert_eq!(lit(&chip8), 0);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        let mut chip8 = machine(&[0xF002, 0xF13A]);
+        chip8.i_register = 0x300;
+        for (i, byte) in chip8.memory[0x300..0x310].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        chip8.registers[1] = 99;
+        run(&mut chip8, 2);
+        assert_eq!(chip8.audio_pattern[15], 15);
+        assert_eq!(chip8.pitch, 99);
+    }
+
+    #[test]
+    fn timers() {
+        let mut chip8 = machine(&[0xF115, 0xF218, 0xF307]);
+        chip8.registers[1] = 10;
+        chip8.registers[2] = 20;
+        run(&mut chip8, 2);
+        assert_eq!(chip8.delay_timer, 10);
+        assert_eq!(chip8.sound_timer, 20);
+        assert!(chip8.sound_active());
+
+        chip8.tick_timers();
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[3], 9);
+    }
+
+    #[test]
+    fn wait_for_key() {
+        let mut chip8 = machine(&[0xF50A]);
+        run(&mut chip8, 3);
+        assert_eq!(chip8.pc, 0x200);
+
+        chip8.keys[0xB] = true;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.registers[5], 0xB);
+    }
+
+    #[test]
+    fn font_addresses() {
+        let mut chip8 = machine(&[0xF129, 0xF130]);
+        chip8.registers[1] = 0x1A;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register as usize, FONT_START + 0xA * 5);
+        assert_eq!(chip8.memory[chip8.i_register as usize], 0xF0);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register as usize, BIG_FONT_START + 0xA * 10);
+    }
+
+    #[test]
+    fn bcd() {
+        for &(value, digits) in [
+            (0, [0, 0, 0]),
+            (7, [0, 0, 7]),
+            (42, [0, 4, 2]),
+            (255, [2, 5, 5]),
+        ]
+        .iter()
+        {
+            let mut chip8 = machine(&[0xF133]);
+            chip8.registers[1] = value;
+            chip8.i_register = 0x300;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.memory[0x300..0x303], digits, "{}", value);
+            assert_eq!(chip8.i_register, 0x300);
+        }
+    }
+
+    #[test]
+    fn bcd_past_end_of_memory() {
+        let mut chip8 = machine(&[0xF133]);
+        chip8.i_register = 0xFFFE;
+        assert!(matches!(
+            chip8.step(),
+            Err(Chip8Error::MemoryOutOfRange { address: 0x10000 })
+        ));
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let mut chip8 = machine(&[0xF255, 0xF265]);
+        chip8.quirks.load_store_increments_i = LoadStoreIncrement::Unchanged;
+        chip8.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        chip8.i_register = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.memory[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(chip8.i_register, 0x300);
+
+        chip8.registers = [0; 16];
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[..4], [1, 2, 3, 0]);
+        assert_eq!(chip8.i_register, 0x300);
+    }
+
+    #[test]
+    fn store_and_load_increment_i_quirk() {
+        let mut chip8 = machine(&[0xF255, 0xF165]);
+        chip8.quirks.load_store_increments_i = LoadStoreIncrement::ByXPlusOne;
+        chip8.registers[..3].copy_from_slice(&[1, 2, 3]);
+        chip8.i_register = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.i_register, 0x303);
+
+        chip8.memory[0x303..0x305].copy_from_slice(&[8, 9]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[..2], [8, 9]);
+        assert_eq!(chip8.i_register, 0x305);
+    }
+
+    #[test]
+    fn store_and_load_chip_48_quirk() {
+        let mut chip8 = machine(&[0xF255, 0xF165]);
+        chip8.quirks = Quirks::CHIP_48;
+        chip8.registers[..3].copy_from_slice(&[1, 2, 3]);
+        chip8.i_register = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(chip8.i_register, 0x302);
+
+        // I is left on the last register stored, so the next load starts there.
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[..2], [3, 0]);
+        assert_eq!(chip8.i_register, 0x303);
+    }
+
+    #[test]
+    fn store_and_load_register_ranges() {
+        let mut chip8 = machine(&[0x5132, 0x5313]);
+        chip8.registers[1..4].copy_from_slice(&[1, 2, 3]);
+        chip8.i_register = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.memory[0x300..0x303], [1, 2, 3]);
+
+        // Counting down when X is greater than Y.
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[1..4], [3, 2, 1]);
+        assert_eq!(chip8.i_register, 0x300);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let mut chip8 = machine(&[0xF275, 0x6000, 0xF285]);
+        chip8.registers[..3].copy_from_slice(&[7, 8, 9]);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers[0], 0);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.registers[..3], [7, 8, 9]);
+    }
+
+    #[test]
+    fn memory_accesses_are_recorded() {
+        let mut chip8 = machine(&[0xF155]);
+        chip8.record_accesses = true;
+        chip8.i_register = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(
+            chip8.accesses,
+            [(0x300, MemoryAccess::Write), (0x301, MemoryAccess::Write)]
+        );
+    }
+
+    #[test]
+    fn timers_tick_at_sixty_hertz() {
+        let mut chip8 = machine(&[0x1200]);
+        chip8.instructions_per_second = 600;
+        chip8.delay_timer = 100;
+        run(&mut chip8, 600);
+        assert_eq!(chip8.delay_timer, 100 - TIMER_HZ as u8);
+    }
+}
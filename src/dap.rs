@@ -0,0 +1,876 @@
+use crate::asm::{self, Assembly};
+use crate::chip8::{Chip8State, MIN_INSTRUCTIONS_PER_SECOND, TIMER_HZ};
+use crate::debugger::{Debugger, StopReason};
+use crate::expr::Expr;
+use crate::quirks::Quirks;
+
+use serde_json::{json, Value};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREAD_ID: i64 = 1;
+
+const REGISTERS_REFERENCE: i64 = 1;
+const STACK_REFERENCE: i64 = 2;
+const MEMORY_REFERENCE: i64 = 3;
+// Each 256 byte page of memory gets its own reference above this.
+const PAGE_REFERENCE: i64 = 0x1000;
+const PAGE_SIZE: usize = 256;
+
+/// Serve the Debug Adapter Protocol over `input` and `output`, e.g. stdin and stdout, until
+/// the client disconnects.
+///
+/// The `launch` request takes either a `program` (a ROM) or a `source` file to assemble, whose
+/// line map lets breakpoints be set by line. It also accepts `quirks`, `ips` and
+/// `stopOnEntry`.
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    Session::new(output).run(requests)
+}
+
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+struct Session<W: Write> {
+    output: W,
+    seq: i64,
+    chip8: Chip8State,
+    debugger: Debugger,
+    assembly: Option<Assembly>,
+    /// Breakpoint addresses set through `setBreakpoints`, by source path.
+    source_breakpoints: HashMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    launched: bool,
+    exited: bool,
+}
+
+impl<W: Write> Session<W> {
+    fn new(output: W) -> Session<W> {
+        let chip8 = Chip8State::new();
+
+        let mut debugger = Debugger::new();
+        debugger.pause();
+
+        Session {
+            output,
+            seq: 1,
+            chip8,
+            debugger,
+            assembly: None,
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: Vec::new(),
+            stop_on_entry: false,
+            launched: false,
+            exited: false,
+        }
+    }
+
+    fn run(&mut self, requests: Receiver<Value>) -> io::Result<()> {
+        let frame = Duration::from_secs(1) / TIMER_HZ;
+        let mut next_frame = Instant::now();
+
+        loop {
+            let running = self.launched && !self.exited && !self.debugger.is_paused();
+
+            let request = if running {
+                let timeout = next_frame.saturating_duration_since(Instant::now());
+                match requests.recv_timeout(timeout) {
+                    Ok(request) => Some(request),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match request {
+                Some(request) => {
+                    if !self.handle(&request)? {
+                        return Ok(());
+                    }
+                    if !running {
+                        next_frame = Instant::now();
+                    }
+                }
+                None => {
+                    next_frame += frame;
+                    self.run_frame()?;
+                }
+            }
+        }
+    }
+
+    fn run_frame(&mut self) -> io::Result<()> {
+        match self.debugger.run_frame(&mut self.chip8) {
+            Ok(Some(reason)) => {
+                let (reason, description) = match reason {
+                    StopReason::Step => ("step", None),
+                    StopReason::Breakpoint(_) => ("breakpoint", None),
+                    StopReason::Watchpoint { .. } => ("data breakpoint", Some(reason.to_string())),
+                    StopReason::Condition(_) => ("breakpoint", Some(reason.to_string())),
+                };
+                self.stopped(reason, description)
+            }
+            Ok(None) if self.chip8.halted => {
+                self.exited = true;
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", json!({}))
+            }
+            Ok(None) => Ok(()),
+            Err(err) => {
+                self.debugger.pause();
+                self.event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("{}\n", err) }),
+                )?;
+                self.stopped("exception", Some(err.to_string()))
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+        }
+
+        self.event("stopped", body)
+    }
+
+    // Handle one request. Returns `false` once the client has disconnected.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let args = &request["arguments"];
+
+        let result = match command.as_str() {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    scope("Registers", REGISTERS_REFERENCE, false),
+                    scope("Stack", STACK_REFERENCE, false),
+                    scope("Memory", MEMORY_REFERENCE, true),
+                ]
+            })),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "readMemory" => self.read_memory(args),
+            "continue" => {
+                self.debugger.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "pause" => {
+                self.debugger.pause();
+                Ok(json!({}))
+            }
+            "stepIn" | "next" | "stepOut" => self.step(&command),
+            "disconnect" | "terminate" => {
+                self.respond(request, &command, Ok(json!({})))?;
+                if command == "terminate" {
+                    self.event("terminated", json!({}))?;
+                }
+                return Ok(false);
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+
+        let succeeded = result.is_ok();
+        self.respond(request, &command, result)?;
+        if !succeeded {
+            return Ok(true);
+        }
+
+        match command.as_str() {
+            "initialize" => self.event("initialized", json!({}))?,
+            "configurationDone" if self.stop_on_entry => self.stopped("entry", None)?,
+            "configurationDone" => self.debugger.resume(),
+            "pause" => self.stopped("pause", None)?,
+            "stepIn" | "next" | "stepOut" if self.debugger.is_paused() => {
+                self.stopped("step", None)?
+            }
+            _ => (),
+        }
+
+        Ok(true)
+    }
+
+    fn respond(
+        &mut self,
+        request: &Value,
+        command: &str,
+        result: Result<Value, String>,
+    ) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+
+        self.send(response)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        if let Some(name) = args["quirks"].as_str() {
+            self.chip8.quirks = Quirks::from_name(name)
+                .ok_or_else(|| format!("unknown quirks preset '{}'", name))?;
+        }
+        if let Some(ips) = args["ips"].as_u64() {
+            if ips < u64::from(MIN_INSTRUCTIONS_PER_SECOND) {
+                return Err(format!(
+                    "ips must be at least {}",
+                    MIN_INSTRUCTIONS_PER_SECOND
+                ));
+            }
+            self.chip8.instructions_per_second = ips as u32;
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        let rom = if let Some(source) = args["source"].as_str() {
+            let assembly = asm::assemble_file(source).map_err(|err| err.to_string())?;
+            let rom = assembly.rom.clone();
+            self.assembly = Some(assembly);
+            rom
+        } else if let Some(program) = args["program"].as_str() {
+            fs::read(program).map_err(|err| format!("unable to read {}: {}", program, err))?
+        } else {
+            return Err("launch needs a 'program' or 'source'".to_string());
+        };
+
+        self.chip8
+            .load_rom_bytes(&rom)
+            .map_err(|err| err.to_string())?;
+        self.launched = true;
+
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+
+        for address in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            self.debugger.remove_breakpoint(address);
+        }
+
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+
+            let (address, actual_line) = match self.address_for_line(&path, line) {
+                Some(found) => found,
+                None => {
+                    results.push(json!({
+                        "verified": false,
+                        "line": line,
+                        "message": "no code at this line",
+                    }));
+                    continue;
+                }
+            };
+
+            match breakpoint["condition"].as_str() {
+                Some(condition) if !condition.trim().is_empty() => match Expr::parse(condition) {
+                    Ok(condition) => self.debugger.add_conditional_breakpoint(address, condition),
+                    Err(err) => {
+                        results.push(json!({
+                            "verified": false,
+                            "line": line,
+                            "message": format!("invalid condition: {}", err),
+                        }));
+                        continue;
+                    }
+                },
+                _ => {
+                    self.debugger.add_breakpoint(address);
+                }
+            }
+
+            addresses.push(address);
+            results.push(json!({
+                "verified": true,
+                "line": actual_line,
+                "instructionReference": format!("{:#05X}", address),
+            }));
+        }
+
+        self.source_breakpoints.insert(path, addresses);
+
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        for address in self.instruction_breakpoints.drain(..) {
+            self.debugger.remove_breakpoint(address);
+        }
+
+        let mut results = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+
+            match parse_reference(reference) {
+                Some(address) => {
+                    let address = (address as i64 + offset) as u16;
+                    self.debugger.add_breakpoint(address);
+                    self.instruction_breakpoints.push(address);
+                    results.push(json!({
+                        "verified": true,
+                        "instructionReference": format!("{:#05X}", address),
+                    }));
+                }
+                None => results.push(json!({ "verified": false })),
+            }
+        }
+
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    // The first instruction at or after `line` in `path`, and the line it is on.
+    fn address_for_line(&self, path: &str, line: usize) -> Option<(u16, usize)> {
+        let assembly = self.assembly.as_ref()?;
+
+        assembly
+            .lines
+            .iter()
+            .filter(|info| same_file(&info.file, path) && info.line >= line)
+            .min_by_key(|info| (info.line, info.address))
+            .map(|info| (info.address, info.line))
+    }
+
+    fn stack_trace(&self) -> Value {
+        let lines: BTreeMap<u16, (&str, usize)> = self
+            .assembly
+            .iter()
+            .flat_map(|assembly| &assembly.lines)
+            .map(|info| (info.address, (info.file.as_str(), info.line)))
+            .collect();
+
+        // The innermost frame is at pc; each outer one is at the call that was made.
+        let sp = (self.chip8.sp as usize).min(self.chip8.stack.len());
+        let mut addresses = vec![self.chip8.pc];
+        addresses.extend(
+            self.chip8.stack[..sp]
+                .iter()
+                .rev()
+                .map(|&address| address.wrapping_sub(2)),
+        );
+
+        let frames: Vec<Value> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, &address)| {
+                let name = match self.chip8.instruction_at(address) {
+                    Some(instruction) => format!("{:#05X}: {}", address, instruction),
+                    None => format!("{:#05X}", address),
+                };
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#05X}", address),
+                });
+                if let Some((file, line)) = lines.get(&address) {
+                    let name = Path::new(file)
+                        .file_name()
+                        .map_or(file.to_string(), |name| name.to_string_lossy().into_owned());
+                    frame["source"] = json!({ "name": name, "path": file });
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": addresses.len() })
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["variablesReference"].as_i64().unwrap_or(0);
+        let chip8 = &self.chip8;
+
+        let variables: Vec<Value> = match reference {
+            REGISTERS_REFERENCE => {
+                let mut variables: Vec<Value> = chip8
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(x, value)| variable(&format!("V{:X}", x), format!("{:#04X}", value)))
+                    .collect();
+                variables.push(variable("I", format!("{:#05X}", chip8.i_register)));
+                variables.push(variable("PC", format!("{:#05X}", chip8.pc)));
+                variables.push(variable("SP", chip8.sp.to_string()));
+                variables.push(variable("DT", chip8.delay_timer.to_string()));
+                variables.push(variable("ST", chip8.sound_timer.to_string()));
+                variables
+            }
+            STACK_REFERENCE => chip8.stack[..(chip8.sp as usize).min(chip8.stack.len())]
+                .iter()
+                .enumerate()
+                .map(|(i, address)| variable(&format!("[{}]", i), format!("{:#05X}", address)))
+                .collect(),
+            MEMORY_REFERENCE => (0..chip8.memory.len() / PAGE_SIZE)
+                .map(|page| {
+                    let start = page * PAGE_SIZE;
+                    let mut page_variable = variable(
+                        &format!("{:#06X}", start),
+                        format!("{:#06X}-{:#06X}", start, start + PAGE_SIZE - 1),
+                    );
+                    page_variable["variablesReference"] = json!(PAGE_REFERENCE + page as i64);
+                    page_variable["memoryReference"] = json!(format!("{:#06X}", start));
+                    page_variable
+                })
+                .collect(),
+            _ if reference >= PAGE_REFERENCE => {
+                let start = (reference - PAGE_REFERENCE) as usize * PAGE_SIZE;
+                let page = chip8
+                    .memory
+                    .get(start..start + PAGE_SIZE)
+                    .ok_or_else(|| "no such memory page".to_string())?;
+                page.chunks(16)
+                    .enumerate()
+                    .map(|(row, bytes)| {
+                        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                        variable(&format!("{:#06X}", start + row * 16), hex.join(" "))
+                    })
+                    .collect()
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        };
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        if args["variablesReference"].as_i64() != Some(REGISTERS_REFERENCE) {
+            return Err("only registers can be changed".to_string());
+        }
+        let name = args["name"].as_str().unwrap_or("");
+        let value = args["value"].as_str().unwrap_or("");
+        let value = Expr::parse(value)
+            .map_err(|err| err.to_string())?
+            .eval(&self.chip8);
+
+        let chip8 = &mut self.chip8;
+        let shown = match name {
+            "I" => {
+                chip8.i_register = value as u16;
+                format!("{:#05X}", chip8.i_register)
+            }
+            "PC" => {
+                chip8.pc = value as u16;
+                format!("{:#05X}", chip8.pc)
+            }
+            "DT" => {
+                chip8.delay_timer = value as u8;
+                chip8.delay_timer.to_string()
+            }
+            "ST" => {
+                chip8.sound_timer = value as u8;
+                chip8.sound_timer.to_string()
+            }
+            _ => {
+                let x = name
+                    .strip_prefix('V')
+                    .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                    .filter(|&x| x < 16)
+                    .ok_or_else(|| format!("'{}' cannot be changed", name))?;
+                chip8.registers[x] = value as u8;
+                format!("{:#04X}", chip8.registers[x])
+            }
+        };
+
+        Ok(json!({ "value": shown }))
+    }
+
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().unwrap_or("");
+        let value = Expr::parse(expression)
+            .map_err(|err| err.to_string())?
+            .eval(&self.chip8);
+
+        Ok(json!({
+            "result": format!("{} ({:#X})", value, value),
+            "variablesReference": 0,
+        }))
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let base = parse_reference(reference)
+            .ok_or_else(|| format!("invalid memory reference '{}'", reference))?;
+        let start = base as i64 + args["offset"].as_i64().unwrap_or(0);
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+
+        let memory = &self.chip8.memory;
+        let start = start.max(0) as usize;
+        let end = start.saturating_add(count).min(memory.len());
+        let bytes = memory.get(start..end).unwrap_or(&[]);
+
+        Ok(json!({
+            "address": format!("{:#06X}", start),
+            "unreadableBytes": count - bytes.len(),
+            "data": base64(bytes),
+        }))
+    }
+
+    fn step(&mut self, command: &str) -> Result<Value, String> {
+        match command {
+            "stepIn" => self
+                .debugger
+                .step(&mut self.chip8)
+                .map_err(|err| err.to_string())?,
+            "next" => self
+                .debugger
+                .step_over(&mut self.chip8)
+                .map_err(|err| err.to_string())?,
+            _ => {
+                if !self.debugger.step_out(&self.chip8) {
+                    return Err("not in a subroutine".to_string());
+                }
+            }
+        }
+
+        Ok(json!({}))
+    }
+}
+
+fn scope(name: &str, reference: i64, expensive: bool) -> Value {
+    json!({ "name": name, "variablesReference": reference, "expensive": expensive })
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+fn parse_reference(reference: &str) -> Option<u16> {
+    let digits = reference
+        .strip_prefix("0x")
+        .or_else(|| reference.strip_prefix("0X"))?;
+
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |value, (i, &b)| value | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(value >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::sync::mpsc::Sender;
+
+    // Pipes between the test and a server thread.
+    struct PipeReader {
+        receiver: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for PipeReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.receiver.recv() {
+                    Ok(bytes) => self.pending = bytes,
+                    Err(_) => return Ok(0),
+                }
+            }
+
+            let count = buf.len().min(self.pending.len());
+            buf[..count].copy_from_slice(&self.pending[..count]);
+            self.pending.drain(..count);
+            Ok(count)
+        }
+    }
+
+    struct PipeWriter(Sender<Vec<u8>>);
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "closed"))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Client {
+        input: Sender<Vec<u8>>,
+        output: Receiver<Vec<u8>>,
+        received: Vec<u8>,
+        seq: i64,
+        server_seq: i64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            let body = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            })
+            .to_string();
+            let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+            self.input.send(framed.into_bytes()).unwrap();
+
+            let response = self.next_message(|message| message["type"] == "response");
+            assert_eq!(response["request_seq"], self.seq);
+            assert_eq!(response["command"], command);
+            assert_eq!(response["success"], true, "{}", response);
+            self.seq += 1;
+
+            response["body"].clone()
+        }
+
+        fn event(&mut self, event: &str) -> Value {
+            let message = self.next_message(|message| message["type"] == "event");
+            assert_eq!(message["event"], event);
+
+            message["body"].clone()
+        }
+
+        fn next_message<F: Fn(&Value) -> bool>(&mut self, wanted: F) -> Value {
+            let message = self.read_message();
+            assert!(wanted(&message), "unexpected message {}", message);
+            message
+        }
+
+        // Read one `Content-Length` framed message, checking its sequence number.
+        fn read_message(&mut self) -> Value {
+            loop {
+                if let Some(end) = find(&self.received, b"\r\n\r\n") {
+                    let header = std::str::from_utf8(&self.received[..end]).unwrap();
+                    let length: usize = header
+                        .strip_prefix("Content-Length: ")
+                        .expect("Content-Length header")
+                        .parse()
+                        .unwrap();
+                    let start = end + 4;
+                    if self.received.len() >= start + length {
+                        let message: Value =
+                            serde_json::from_slice(&self.received[start..start + length]).unwrap();
+                        self.received.drain(..start + length);
+
+                        assert_eq!(message["seq"], self.server_seq);
+                        self.server_seq += 1;
+                        return message;
+                    }
+                }
+
+                let bytes = self
+                    .output
+                    .recv_timeout(Duration::from_secs(5))
+                    .expect("no message from the server");
+                self.received.extend_from_slice(&bytes);
+            }
+        }
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    #[test]
+    fn scripted_session() {
+        let source = env::temp_dir().join(format!("chip8-dap-{}.s", std::process::id()));
+        fs::write(
+            &source,
+            "start:\n    LD V0, 5\nloop:\n    ADD V0, 1\n    JP loop\n",
+        )
+        .unwrap();
+        let path = source.to_string_lossy().into_owned();
+
+        let (input, requests) = mpsc::channel();
+        let (responses, output) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let reader = PipeReader {
+                receiver: requests,
+                pending: Vec::new(),
+            };
+            serve(reader, PipeWriter(responses))
+        });
+        let mut client = Client {
+            input,
+            output,
+            received: Vec::new(),
+            seq: 1,
+            server_seq: 1,
+        };
+
+        let capabilities = client.request("initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(capabilities["supportsReadMemoryRequest"], true);
+        client.event("initialized");
+
+        client.request("launch", json!({ "source": path }));
+        let breakpoints = client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 4 }] }),
+        );
+        assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+        assert_eq!(
+            breakpoints["breakpoints"][0]["instructionReference"],
+            "0x202"
+        );
+
+        client.request("configurationDone", json!({}));
+        let stopped = client.event("stopped");
+        assert_eq!(stopped["reason"], "breakpoint");
+
+        let trace = client.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        let frame = &trace["stackFrames"][0];
+        assert_eq!(frame["line"], 4);
+        assert_eq!(frame["instructionPointerReference"], "0x202");
+        assert_eq!(frame["source"]["path"], path.as_str());
+
+        let registers = client.request(
+            "variables",
+            json!({ "variablesReference": REGISTERS_REFERENCE }),
+        );
+        assert_eq!(registers["variables"][0]["name"], "V0");
+        assert_eq!(registers["variables"][0]["value"], "0x05");
+
+        let memory = client.request(
+            "readMemory",
+            json!({ "memoryReference": "0x200", "count": 4 }),
+        );
+        assert_eq!(memory["address"], "0x0200");
+        assert_eq!(memory["data"], "YAVwAQ==");
+
+        client.request("disconnect", json!({}));
+        server.join().unwrap().unwrap();
+        fs::remove_file(&source).unwrap();
+    }
+
+    #[test]
+    fn launch_rejects_slow_clocks() {
+        let mut session = Session::new(Vec::new());
+        assert_eq!(
+            session.launch(&json!({ "ips": 10 })),
+            Err("ips must be at least 60".to_string())
+        );
+    }
+}
@@ -0,0 +1,735 @@
+use crate::chip8::{Chip8State, MemoryAccess};
+use crate::error::Chip8Error;
+use crate::expr::Expr;
+use crate::instruction::Instruction;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+/// Why the debugger paused emulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u16),
+    /// The instruction at `pc` touched `address`, which watchpoint number `index` covers.
+    Watchpoint {
+        index: usize,
+        pc: u16,
+        address: usize,
+        access: MemoryAccess,
+    },
+    /// Condition number `index` became true.
+    Condition(usize),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "stepped"),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at {:#05X}", address),
+            StopReason::Watchpoint {
+                index,
+                pc,
+                address,
+                access,
+            } => {
+                let access = match access {
+                    MemoryAccess::Read => "read",
+                    MemoryAccess::Write => "write",
+                };
+                write!(
+                    f,
+                    "watchpoint {}: {} of {:#05X} by instruction at {:#05X}",
+                    index, access, address, pc
+                )
+            }
+            StopReason::Condition(index) => write!(f, "condition {} became true", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    /// Running until the stack is no deeper than `depth`.
+    Until {
+        depth: u8,
+    },
+}
+
+/// Stops when an instruction reads or writes memory between `start` and `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, address: usize, access: MemoryAccess) -> bool {
+        let wanted = match access {
+            MemoryAccess::Read => self.read,
+            MemoryAccess::Write => self.write,
+        };
+
+        wanted && self.start as usize <= address && address <= self.end as usize
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{:#05X}-{:#05X} {}", self.start, self.end, access)
+    }
+}
+
+/// Controls execution of a `Chip8State`: pausing, stepping, breakpoints and watchpoints.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    /// Breakpoints by address, with the condition that must also hold for them to fire.
+    breakpoints: BTreeMap<u16, Option<Expr>>,
+    watchpoints: Vec<Watchpoint>,
+    /// Conditions checked before every instruction, with whether each held last time.
+    conditions: Vec<(Expr, bool)>,
+    state: RunState,
+    // Set when resuming from a breakpoint so the instruction it stopped on can run.
+    resuming: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            state: RunState::Running,
+            resuming: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.state = RunState::Running;
+        self.resuming = true;
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<u16, Option<Expr>> {
+        &self.breakpoints
+    }
+
+    /// Returns `false` if there was already a breakpoint at `address`.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address, None).is_none()
+    }
+
+    /// Add a breakpoint that only fires when `condition` holds, replacing any at `address`.
+    pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Expr) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    /// Returns `false` if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Returns the index of the new watchpoint.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Expr> {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    /// Stop whenever `condition` changes from false to true, wherever `pc` is. Returns the
+    /// index of the new condition.
+    pub fn add_condition(&mut self, chip8: &Chip8State, condition: Expr) -> usize {
+        let held = condition.holds(chip8);
+        self.conditions.push((condition, held));
+        self.conditions.len() - 1
+    }
+
+    pub fn remove_condition(&mut self, index: usize) -> Option<Expr> {
+        if index < self.conditions.len() {
+            Some(self.conditions.remove(index).0)
+        } else {
+            None
+        }
+    }
+
+    // Update every condition, returning the first that has just become true.
+    fn check_conditions(&mut self, chip8: &Chip8State) -> Option<usize> {
+        let mut fired = None;
+        for (index, (condition, held)) in self.conditions.iter_mut().enumerate() {
+            let holds = condition.holds(chip8);
+            if holds && !*held && fired.is_none() {
+                fired = Some(index);
+            }
+            *held = holds;
+        }
+
+        fired
+    }
+
+    fn breakpoint_hit(&self, chip8: &Chip8State) -> bool {
+        match self.breakpoints.get(&chip8.pc) {
+            Some(Some(condition)) => condition.holds(chip8),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Execute a single instruction and pause.
+    pub fn step(&mut self, chip8: &mut Chip8State) -> Result<(), Chip8Error> {
+        self.state = RunState::Paused;
+        chip8.step()?;
+
+        Ok(())
+    }
+
+    /// Like `step`, but a `2NNN` call runs until its subroutine returns.
+    pub fn step_over(&mut self, chip8: &mut Chip8State) -> Result<(), Chip8Error> {
+        match chip8.instruction_at(chip8.pc) {
+            Some(Instruction::Call(_)) if !chip8.halted => {
+                self.state = RunState::Until { depth: chip8.sp };
+                self.resuming = true;
+                Ok(())
+            }
+            _ => self.step(chip8),
+        }
+    }
+
+    /// Run until the current subroutine returns. Returns `false` outside of a subroutine.
+    pub fn step_out(&mut self, chip8: &Chip8State) -> bool {
+        if chip8.sp == 0 {
+            return false;
+        }
+
+        self.state = RunState::Until {
+            depth: chip8.sp - 1,
+        };
+        self.resuming = true;
+        true
+    }
+
+    /// Run until 1/60th of a second of emulated time has passed, as `Chip8State::run_frame`
+    /// does, unless a breakpoint or step finishes first.
+    ///
+    /// Returns why emulation stopped, if it did. Nothing runs while paused.
+    pub fn run_frame(&mut self, chip8: &mut Chip8State) -> Result<Option<StopReason>, Chip8Error> {
+        loop {
+            if self.state == RunState::Paused {
+                return Ok(None);
+            }
+
+            let resuming = self.resuming;
+            self.resuming = false;
+            if !resuming && !chip8.halted && self.breakpoint_hit(chip8) {
+                self.state = RunState::Paused;
+                return Ok(Some(StopReason::Breakpoint(chip8.pc)));
+            }
+
+            let pc = chip8.pc;
+            chip8.record_accesses = !self.watchpoints.is_empty();
+            let ticked = chip8.step()?;
+
+            if let Some(reason) = self.watchpoint_hit(chip8, pc) {
+                self.state = RunState::Paused;
+                return Ok(Some(reason));
+            }
+
+            if let Some(index) = self.check_conditions(chip8) {
+                self.state = RunState::Paused;
+                return Ok(Some(StopReason::Condition(index)));
+            }
+
+            if let RunState::Until { depth } = self.state {
+                if chip8.sp <= depth {
+                    self.state = RunState::Paused;
+                    return Ok(Some(StopReason::Step));
+                }
+            }
+
+            if ticked {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn watchpoint_hit(&self, chip8: &Chip8State, pc: u16) -> Option<StopReason> {
+        chip8.accesses.iter().find_map(|&(address, access)| {
+            let index = self
+                .watchpoints
+                .iter()
+                .position(|watchpoint| watchpoint.matches(address, access))?;
+
+            Some(StopReason::Watchpoint {
+                index,
+                pc,
+                address,
+                access,
+            })
+        })
+    }
+
+    /// Run a console command, returning what to print.
+    pub fn command(&mut self, chip8: &mut Chip8State, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(String::new()),
+        };
+        let args: Vec<&str> = words.collect();
+
+        match name {
+            "c" | "continue" => {
+                self.resume();
+                Ok(String::new())
+            }
+            "p" | "pause" => {
+                self.pause();
+                Ok(describe_pc(chip8))
+            }
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("invalid count '{}'", count))?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    self.step(chip8).map_err(|err| err.to_string())?;
+                }
+                Ok(describe_pc(chip8))
+            }
+            "n" | "next" => {
+                self.step_over(chip8).map_err(|err| err.to_string())?;
+                Ok(if self.is_paused() {
+                    describe_pc(chip8)
+                } else {
+                    String::new()
+                })
+            }
+            "o" | "out" | "finish" => {
+                if self.step_out(chip8) {
+                    Ok(String::new())
+                } else {
+                    Err("not in a subroutine".to_string())
+                }
+            }
+            "b" | "break" => {
+                let address = parse_address(&args)?;
+                match args.get(1) {
+                    Some(&"if") => {
+                        let condition = parse_expr(&args[2..])?;
+                        let message =
+                            format!("Breakpoint set at {:#05X} if {}", address, condition);
+                        self.add_conditional_breakpoint(address, condition);
+                        Ok(message)
+                    }
+                    Some(_) => Err("expected 'if' after the address".to_string()),
+                    None if self.add_breakpoint(address) => {
+                        Ok(format!("Breakpoint set at {:#05X}", address))
+                    }
+                    None => Ok(format!("Breakpoint already set at {:#05X}", address)),
+                }
+            }
+            "w" | "watch" => {
+                let range = args
+                    .first()
+                    .ok_or_else(|| "expected an address range".to_string())?;
+                let (start, end) = match range.find('-') {
+                    Some(dash) => (
+                        parse_address(&[&range[..dash]])?,
+                        parse_address(&[&range[dash + 1..]])?,
+                    ),
+                    None => {
+                        let address = parse_address(&[range])?;
+                        (address, address)
+                    }
+                };
+                let (read, write) = match args.get(1).copied() {
+                    None | Some("w") => (false, true),
+                    Some("r") => (true, false),
+                    Some("rw") => (true, true),
+                    Some(other) => return Err(format!("expected r, w or rw, not '{}'", other)),
+                };
+                if start > end {
+                    return Err("watchpoint range ends before it starts".to_string());
+                }
+
+                let watchpoint = Watchpoint {
+                    start,
+                    end,
+                    read,
+                    write,
+                };
+                let index = self.add_watchpoint(watchpoint);
+                Ok(format!("Watchpoint {} set on {}", index, watchpoint))
+            }
+            "dw" => {
+                let index = parse_index(&args)?;
+                match self.remove_watchpoint(index) {
+                    Some(_) => Ok(format!("Watchpoint {} removed", index)),
+                    None => Err(format!("no watchpoint {}", index)),
+                }
+            }
+            "cond" => {
+                let condition = parse_expr(&args)?;
+                let message = format!("Stopping when {}", condition);
+                let index = self.add_condition(chip8, condition);
+                Ok(format!("Condition {}: {}", index, message))
+            }
+            "dc" => {
+                let index = parse_index(&args)?;
+                match self.remove_condition(index) {
+                    Some(_) => Ok(format!("Condition {} removed", index)),
+                    None => Err(format!("no condition {}", index)),
+                }
+            }
+            "e" | "eval" => {
+                let value = parse_expr(&args)?.eval(chip8);
+                Ok(format!("{} ({:#X})", value, value))
+            }
+            "d" | "delete" => {
+                let address = parse_address(&args)?;
+                if self.remove_breakpoint(address) {
+                    Ok(format!("Breakpoint removed from {:#05X}", address))
+                } else {
+                    Err(format!("no breakpoint at {:#05X}", address))
+                }
+            }
+            "bl" | "breakpoints" => {
+                let mut out = String::new();
+                for (address, condition) in &self.breakpoints {
+                    match condition {
+                        Some(condition) => {
+                            writeln!(out, "break {:#05X} if {}", address, condition).unwrap()
+                        }
+                        None => writeln!(out, "break {:#05X}", address).unwrap(),
+                    }
+                }
+                for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+                    writeln!(out, "watch {}: {}", index, watchpoint).unwrap();
+                }
+                for (index, condition) in self.conditions().enumerate() {
+                    writeln!(out, "cond {}: {}", index, condition).unwrap();
+                }
+                Ok(out)
+            }
+            "r" | "regs" | "registers" => Ok(dump_state(chip8)),
+            "l" | "list" => Ok(listing(chip8, &self.breakpoints, 4, 8).join("\n")),
+            "x" | "mem" => {
+                let address = parse_address(&args)?;
+                let length = match args.get(1) {
+                    Some(length) => length
+                        .parse()
+                        .map_err(|_| format!("invalid length '{}'", length))?,
+                    None => 16,
+                };
+                Ok(dump_memory(chip8, address, length))
+            }
+            "h" | "help" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command '{}', try 'help'", name)),
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+const HELP: &str = "Commands:
+    c, continue        resume emulation
+    p, pause           pause emulation
+    s, step [N]        execute N instructions (default 1)
+    n, next            step, running 2NNN calls to completion
+    o, out             run until the current subroutine returns
+    b, break ADDR      set a breakpoint
+    b ADDR if EXPR     set a breakpoint that fires only when EXPR holds
+    d, delete ADDR     remove a breakpoint
+    w, watch START[-END] [r|w|rw]
+                       stop when memory is read or written (default w)
+    dw N               remove watchpoint N
+    cond EXPR          stop whenever EXPR becomes true
+    dc N               remove condition N
+    bl, breakpoints    list breakpoints, watchpoints and conditions
+    e, eval EXPR       show the value of EXPR
+    r, regs            show registers, I, stack and timers
+    l, list            disassemble around pc
+    x, mem ADDR [LEN]  show LEN bytes of memory (default 16)
+Addresses are hexadecimal. Expressions use V0-VF, I, PC, SP, DT, ST, [ADDR] for memory,
+numbers in decimal or 0x hex, arithmetic, comparisons, && and ||, e.g. V3 == 0x10 && I > 0x300";
+
+fn parse_address(args: &[&str]) -> Result<u16, String> {
+    let arg = args
+        .first()
+        .ok_or_else(|| "expected an address".to_string())?;
+    let digits = arg
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", arg))
+}
+
+fn parse_index(args: &[&str]) -> Result<usize, String> {
+    let arg = args
+        .first()
+        .ok_or_else(|| "expected a number".to_string())?;
+
+    arg.parse().map_err(|_| format!("invalid number '{}'", arg))
+}
+
+fn parse_expr(args: &[&str]) -> Result<Expr, String> {
+    if args.is_empty() {
+        return Err("expected an expression".to_string());
+    }
+    let source = args.join(" ");
+
+    Expr::parse(&source).map_err(|err| format!("in '{}' at {}", source, err))
+}
+
+/// The address and disassembly of the next instruction.
+pub fn describe_pc(chip8: &Chip8State) -> String {
+    match chip8.instruction_at(chip8.pc) {
+        Some(instruction) => format!("{:#05X}: {}", chip8.pc, instruction),
+        None => format!("{:#05X}: unknown opcode", chip8.pc),
+    }
+}
+
+/// Disassembly of `before` instructions before `pc` and `after` from it, assuming the code
+/// is two-byte aligned. The line at `pc` is marked with `>` and breakpoints with `*`.
+pub fn listing(
+    chip8: &Chip8State,
+    breakpoints: &BTreeMap<u16, Option<Expr>>,
+    before: u16,
+    after: u16,
+) -> Vec<String> {
+    let start = chip8.pc.saturating_sub(before * 2);
+    let mut address = start;
+    let mut lines = Vec::new();
+
+    while lines.len() < (before + after) as usize && (address as usize) + 1 < chip8.memory.len() {
+        let marker = if address == chip8.pc { '>' } else { ' ' };
+        let breakpoint = if breakpoints.contains_key(&address) {
+            '*'
+        } else {
+            ' '
+        };
+        let (text, size) = match chip8.instruction_at(address) {
+            Some(instruction) => (instruction.to_string(), instruction.size()),
+            None => {
+                let word = (chip8.memory[address as usize] as u16) << 8
+                    | chip8.memory[address as usize + 1] as u16;
+                (format!("db {:#06X}", word), 2)
+            }
+        };
+
+        lines.push(format!(
+            "{}{} {:03X}: {}",
+            marker, breakpoint, address, text
+        ));
+        address = match address.checked_add(size) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    lines
+}
+
+/// Registers, I, timers and the stack, one group per line.
+pub fn dump_state(chip8: &Chip8State) -> String {
+    let mut out = format!(
+        "pc: {:#05X}, I: {:#05X}, sp: {}, delay: {}, sound: {}\n",
+        chip8.pc, chip8.i_register, chip8.sp, chip8.delay_timer, chip8.sound_timer
+    );
+    for (i, value) in chip8.registers.iter().enumerate() {
+        write!(out, "V{:X}: {:#04X} ", i, value).unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "stack: {:03X?}", &chip8.stack[..chip8.sp as usize]).unwrap();
+
+    out
+}
+
+/// A hex dump of `length` bytes of memory, 16 to a line.
+pub fn dump_memory(chip8: &Chip8State, address: u16, length: usize) -> String {
+    let start = (address as usize).min(chip8.memory.len());
+    let end = start.saturating_add(length).min(chip8.memory.len());
+    let mut out = String::new();
+
+    for (line, bytes) in chip8.memory[start..end].chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(out, "{:04X}: {}", start + line * 16, hex.join(" ")).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 1; CALL 0x208; ADD V0, 1; JP 0x206; LD V1, 5; RET
+    const CALL: [u8; 12] = [
+        0x60, 0x01, 0x22, 0x08, 0x70, 0x01, 0x12, 0x06, 0x61, 0x05, 0x00, 0xEE,
+    ];
+
+    fn call_rom() -> (Chip8State, Debugger) {
+        (Chip8State::with_rom(&CALL).unwrap(), Debugger::new())
+    }
+
+    #[test]
+    fn breakpoint_and_continue() {
+        let (mut chip8, mut debugger) = call_rom();
+        debugger.command(&mut chip8, "b 204").unwrap();
+
+        assert_eq!(
+            debugger.run_frame(&mut chip8).unwrap(),
+            Some(StopReason::Breakpoint(0x204))
+        );
+        assert_eq!(
+            (chip8.pc, chip8.registers[0], chip8.registers[1]),
+            (0x204, 1, 5)
+        );
+        assert_eq!(debugger.run_frame(&mut chip8).unwrap(), None);
+        assert_eq!(chip8.pc, 0x204);
+
+        debugger.command(&mut chip8, "c").unwrap();
+        assert_eq!(debugger.run_frame(&mut chip8).unwrap(), None);
+        assert_eq!((chip8.pc, chip8.registers[0]), (0x206, 2));
+
+        debugger.command(&mut chip8, "d 204").unwrap();
+        assert!(debugger.command(&mut chip8, "d 204").is_err());
+    }
+
+    #[test]
+    fn step_and_step_over() {
+        let (mut chip8, mut debugger) = call_rom();
+        debugger.command(&mut chip8, "p").unwrap();
+        assert_eq!(debugger.run_frame(&mut chip8).unwrap(), None);
+        assert_eq!(chip8.pc, 0x200);
+
+        debugger.command(&mut chip8, "s").unwrap();
+        assert_eq!(chip8.pc, 0x202);
+        assert!(debugger.is_paused());
+
+        // The call runs to completion on the next frame.
+        assert_eq!(debugger.command(&mut chip8, "n").unwrap(), "");
+        assert_eq!(
+            debugger.run_frame(&mut chip8).unwrap(),
+            Some(StopReason::Step)
+        );
+        assert_eq!((chip8.pc, chip8.sp, chip8.registers[1]), (0x204, 0, 5));
+
+        // Anything other than a call is a single step.
+        debugger.command(&mut chip8, "n").unwrap();
+        assert_eq!((chip8.pc, chip8.registers[0]), (0x206, 2));
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn step_into_and_out() {
+        let (mut chip8, mut debugger) = call_rom();
+        assert_eq!(
+            debugger.command(&mut chip8, "o"),
+            Err("not in a subroutine".to_string())
+        );
+
+        debugger.command(&mut chip8, "s 2").unwrap();
+        assert_eq!((chip8.pc, chip8.sp), (0x208, 1));
+
+        debugger.command(&mut chip8, "o").unwrap();
+        assert_eq!(
+            debugger.run_frame(&mut chip8).unwrap(),
+            Some(StopReason::Step)
+        );
+        assert_eq!((chip8.pc, chip8.sp), (0x204, 0));
+    }
+
+    #[test]
+    fn bad_commands() {
+        let (mut chip8, mut debugger) = call_rom();
+
+        assert!(debugger.command(&mut chip8, "b").is_err());
+        assert!(debugger.command(&mut chip8, "b zz").is_err());
+        assert!(debugger.command(&mut chip8, "s many").is_err());
+        assert!(debugger.command(&mut chip8, "frobnicate").is_err());
+        assert_eq!(debugger.command(&mut chip8, "").unwrap(), "");
+    }
+
+    // LD I, 0x300; LD V0, 7; LD [I], V0; JP 0x206
+    const STORE: [u8; 8] = [0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x12, 0x06];
+
+    #[test]
+    fn watchpoint_stops_on_store() {
+        let mut chip8 = Chip8State::with_rom(&STORE).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.command(&mut chip8, "w 2FF-300").unwrap();
+
+        assert_eq!(
+            debugger.run_frame(&mut chip8).unwrap(),
+            Some(StopReason::Watchpoint {
+                index: 0,
+                pc: 0x204,
+                address: 0x300,
+                access: MemoryAccess::Write,
+            })
+        );
+        assert!(debugger.is_paused());
+        assert_eq!(chip8.memory[0x300], 7);
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn read_watchpoints_ignore_stores() {
+        let mut chip8 = Chip8State::with_rom(&STORE).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.command(&mut chip8, "w 300 r").unwrap();
+
+        assert_eq!(debugger.run_frame(&mut chip8).unwrap(), None);
+        assert!(!debugger.is_paused());
+    }
+
+    #[test]
+    fn memory_dumps_are_clamped() {
+        let mut chip8 = Chip8State::new();
+        chip8.memory[0xFFFF] = 0xAB;
+
+        let dump = dump_memory(&chip8, 0xFFF0, usize::MAX);
+        assert_eq!(dump.lines().count(), 1);
+        assert!(dump.starts_with("FFF0: 00"));
+        assert!(dump.trim_end().ends_with("AB"));
+    }
+}
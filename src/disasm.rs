@@ -0,0 +1,397 @@
+use crate::chip8::PROGRAM_START;
+use crate::instruction::{Instruction, LONG_PREFIX};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+const MAX_DATA_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisasmMode {
+    /// Decode every two bytes from the start of the ROM.
+    Linear,
+    /// Follow jumps, calls and skips from the entry point, treating unreached bytes as data.
+    Recursive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Code {
+        address: u16,
+        opcode: u16,
+        instruction: Instruction,
+    },
+    Data {
+        address: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+impl Item {
+    pub fn address(&self) -> u16 {
+        match self {
+            Item::Code { address, .. } => *address,
+            Item::Data { address, .. } => *address,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    pub items: Vec<Item>,
+    pub labels: BTreeSet<u16>,
+}
+
+pub fn disassemble(rom: &[u8], mode: DisasmMode) -> Disassembly {
+    // A jump into the middle of an instruction can make code overlap. Only the first of two
+    // overlapping instructions is shown.
+    let code = match mode {
+        DisasmMode::Linear => linear_sweep(rom),
+        DisasmMode::Recursive => recursive_descent(rom),
+    };
+
+    // Only targets where an item will start get a label. Data is split at labels, so any
+    // data byte can start one, but the middle of an instruction or outside the ROM can't.
+    let mut targets = BTreeSet::new();
+    let mut starts = vec![false; rom.len()];
+    let mut offset = 0;
+    while offset < rom.len() {
+        starts[offset] = true;
+        if let Some(instruction) = code[offset] {
+            if let Some(target) = jump_target(instruction) {
+                targets.insert(target);
+            }
+            offset += instruction.size() as usize;
+        } else {
+            offset += 1;
+        }
+    }
+    let labels: BTreeSet<u16> = targets
+        .into_iter()
+        .filter(|&target| {
+            matches!((target as usize).checked_sub(PROGRAM_START),
+                Some(offset) if offset < rom.len() && starts[offset])
+        })
+        .collect();
+
+    let mut items = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    let mut data_start = 0;
+    offset = 0;
+
+    while offset < rom.len() {
+        let address = (PROGRAM_START + offset) as u16;
+
+        if let Some(instruction) = code[offset] {
+            if !data.is_empty() {
+                items.push(Item::Data {
+                    address: (PROGRAM_START + data_start) as u16,
+                    bytes: data.split_off(0),
+                });
+            }
+
+            items.push(Item::Code {
+                address,
+                opcode: instruction.encode(),
+                instruction,
+            });
+            offset += instruction.size() as usize;
+        } else {
+            if !data.is_empty() && labels.contains(&address) {
+                items.push(Item::Data {
+                    address: (PROGRAM_START + data_start) as u16,
+                    bytes: data.split_off(0),
+                });
+            }
+            if data.is_empty() {
+                data_start = offset;
+            }
+            data.push(rom[offset]);
+            if data.len() == MAX_DATA_LINE {
+                items.push(Item::Data {
+                    address: (PROGRAM_START + data_start) as u16,
+                    bytes: data.split_off(0),
+                });
+            }
+            offset += 1;
+        }
+    }
+
+    if !data.is_empty() {
+        items.push(Item::Data {
+            address: (PROGRAM_START + data_start) as u16,
+            bytes: data,
+        });
+    }
+
+    Disassembly { items, labels }
+}
+
+fn word_at(rom: &[u8], offset: usize) -> Option<u16> {
+    if offset + 1 < rom.len() {
+        Some((rom[offset] as u16) << 8 | rom[offset + 1] as u16)
+    } else {
+        None
+    }
+}
+
+fn decode_at(rom: &[u8], offset: usize) -> Option<Instruction> {
+    let opcode = word_at(rom, offset)?;
+
+    if opcode == LONG_PREFIX {
+        Instruction::decode_pair(opcode, word_at(rom, offset + 2)?)
+    } else {
+        Instruction::decode(opcode)
+    }
+}
+
+// Both passes find the instruction starting at each offset that is code.
+fn linear_sweep(rom: &[u8]) -> Vec<Option<Instruction>> {
+    let mut code = vec![None; rom.len()];
+
+    let mut offset = 0;
+    while offset + 1 < rom.len() {
+        match decode_at(rom, offset) {
+            Some(instruction) => {
+                code[offset] = Some(instruction);
+                offset += instruction.size() as usize;
+            }
+            None => offset += 2,
+        }
+    }
+
+    code
+}
+
+fn recursive_descent(rom: &[u8]) -> Vec<Option<Instruction>> {
+    let mut code = vec![None; rom.len()];
+    let mut visited = vec![false; rom.len()];
+    let mut pending = vec![PROGRAM_START as u16];
+
+    while let Some(address) = pending.pop() {
+        let mut address = address;
+
+        loop {
+            let offset = match (address as usize).checked_sub(PROGRAM_START) {
+                Some(offset) if offset < rom.len() => offset,
+                _ => break,
+            };
+            if visited[offset] {
+                break;
+            }
+            visited[offset] = true;
+
+            let instruction = match decode_at(rom, offset) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            code[offset] = Some(instruction);
+
+            match instruction {
+                Instruction::Jp(target) | Instruction::JpV0(target) => {
+                    pending.push(target);
+                    break;
+                }
+                Instruction::Call(target) => pending.push(target),
+                Instruction::Ret | Instruction::Exit | Instruction::Sys(_) => break,
+                Instruction::SeImm { .. }
+                | Instruction::SneImm { .. }
+                | Instruction::SeReg { .. }
+                | Instruction::SneReg { .. }
+                | Instruction::Skp(_)
+                | Instruction::Sknp(_) => {
+                    // Skips jump over the whole of a following `F000 NNNN`.
+                    let next = offset + 2;
+                    let skipped = if word_at(rom, next) == Some(LONG_PREFIX) {
+                        4
+                    } else {
+                        2
+                    };
+                    pending.push(address.wrapping_add(2 + skipped));
+                }
+                _ => (),
+            }
+
+            address = address.wrapping_add(instruction.size());
+        }
+    }
+
+    code
+}
+
+fn jump_target(instruction: Instruction) -> Option<u16> {
+    match instruction {
+        Instruction::Jp(target) | Instruction::Call(target) | Instruction::JpV0(target) => {
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+pub fn label_name(address: u16) -> String {
+    format!("L{:03X}", address)
+}
+
+fn mnemonic(instruction: Instruction, labels: &BTreeSet<u16>) -> String {
+    match instruction {
+        Instruction::Jp(target) if labels.contains(&target) => {
+            format!("JP {}", label_name(target))
+        }
+        Instruction::Call(target) if labels.contains(&target) => {
+            format!("CALL {}", label_name(target))
+        }
+        Instruction::JpV0(target) if labels.contains(&target) => {
+            format!("JP V0, {}", label_name(target))
+        }
+        _ => instruction.to_string(),
+    }
+}
+
+/// Prints the disassembly as source the assembler accepts, with each item's address and raw
+/// bytes in a comment.
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            if self.labels.contains(&item.address()) {
+                writeln!(f, "{}:", label_name(item.address()))?;
+            }
+
+            let (address, raw, text) = match item {
+                Item::Code {
+                    address,
+                    instruction,
+                    ..
+                } => {
+                    let raw: Vec<String> = instruction
+                        .to_bytes()
+                        .chunks(2)
+                        .map(|word| format!("{:02X}{:02X}", word[0], word[1]))
+                        .collect();
+                    (address, raw.join(" "), mnemonic(*instruction, &self.labels))
+                }
+                Item::Data { address, bytes } => {
+                    let raw: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    let values: Vec<String> = bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                    (address, raw.join(""), format!("db {}", values.join(", ")))
+                }
+            };
+            writeln!(f, "    {:<24} ; {:03X}: {}", text, address, raw)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(address: u16, instruction: Instruction) -> Item {
+        Item::Code {
+            address,
+            opcode: instruction.encode(),
+            instruction,
+        }
+    }
+
+    fn data(address: u16, bytes: &[u8]) -> Item {
+        Item::Data {
+            address,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn linear_decodes_every_word() {
+        let rom = [0x00, 0xE0, 0xFF, 0xFF, 0xAB, 0xCD, 0x12, 0x00];
+        let disassembly = disassemble(&rom, DisasmMode::Linear);
+
+        assert_eq!(
+            disassembly.items,
+            [
+                code(0x200, Instruction::Cls),
+                data(0x202, &[0xFF, 0xFF]),
+                code(0x204, Instruction::LdI(0xBCD)),
+                code(0x206, Instruction::Jp(0x200)),
+            ]
+        );
+        assert!(disassembly.labels.contains(&0x200));
+    }
+
+    #[test]
+    fn recursive_skips_unreached_bytes() {
+        let rom = [0x12, 0x04, 0xAB, 0xCD, 0x00, 0xE0, 0x12, 0x04];
+        let disassembly = disassemble(&rom, DisasmMode::Recursive);
+
+        assert_eq!(
+            disassembly.items,
+            [
+                code(0x200, Instruction::Jp(0x204)),
+                data(0x202, &[0xAB, 0xCD]),
+                code(0x204, Instruction::Cls),
+                code(0x206, Instruction::Jp(0x204)),
+            ]
+        );
+        assert!(disassembly
+            .to_string()
+            .contains("L204:\n    CLS                      ; 204: 00E0\n"));
+    }
+
+    #[test]
+    fn recursive_follows_skips_and_long_loads() {
+        // SE V0, 0 skips the four byte LD I, then returns.
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xEE];
+        let disassembly = disassemble(&rom, DisasmMode::Recursive);
+
+        assert_eq!(
+            disassembly.items,
+            [
+                code(0x200, Instruction::SeImm { x: 0, nn: 0 }),
+                code(0x202, Instruction::LdILong(0x1234)),
+                code(0x206, Instruction::Ret),
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_into_the_middle_of_an_instruction() {
+        // JP 0x201 lands on the second byte of the jump itself.
+        let rom = [0x12, 0x01, 0x00];
+
+        for &mode in &[DisasmMode::Linear, DisasmMode::Recursive] {
+            let disassembly = disassemble(&rom, mode);
+            assert_eq!(
+                disassembly.items,
+                [code(0x200, Instruction::Jp(0x201)), data(0x202, &[0x00])]
+            );
+            assert!(disassembly.labels.is_empty());
+            assert!(disassembly.to_string().contains("JP 0x201 "));
+        }
+    }
+
+    #[test]
+    fn targets_outside_the_rom_have_no_label() {
+        let disassembly = disassemble(&[0x11, 0x00, 0x22, 0x04], DisasmMode::Linear);
+
+        assert!(disassembly.labels.is_empty());
+        assert!(disassembly.to_string().contains("JP 0x100 "));
+        assert!(disassembly.to_string().contains("CALL 0x204 "));
+    }
+
+    #[test]
+    fn reassembles_to_the_same_rom() {
+        let rom = [
+            0x60, 0x05, 0xA2, 0x0C, 0x22, 0x08, 0x12, 0x06, 0xD0, 0x15, 0x00, 0xEE, 0xF0, 0x90,
+            0x90, 0x90, 0xF0, 0x12, 0x01,
+        ];
+
+        for &rom in &[&rom[..], &[0x12, 0x01, 0x00], &[0x11, 0x00]] {
+            for &mode in &[DisasmMode::Linear, DisasmMode::Recursive] {
+                let source = disassemble(rom, mode).to_string();
+                let assembly = crate::asm::assemble(&source)
+                    .unwrap_or_else(|err| panic!("{}\n{}", err, source));
+                assert_eq!(assembly.rom, rom, "{}", source);
+            }
+        }
+    }
+}
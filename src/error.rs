@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Chip8Error {
+    UnknownOpcode { opcode: u16, address: u16 },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    MemoryOutOfRange { address: usize },
+    RomTooLarge { size: usize, max: usize },
+    RomUnreadable(io::Error),
+    TraceUnwritable(io::Error),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { opcode, address } => {
+                write!(
+                    f,
+                    "unknown opcode {:04X} at address {:#05X}",
+                    opcode, address
+                )
+            }
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "stack overflow at address {:#05X}", address)
+            }
+            Chip8Error::StackUnderflow { address } => {
+                write!(f, "stack underflow at address {:#05X}", address)
+            }
+            Chip8Error::MemoryOutOfRange { address } => {
+                write!(f, "memory access out of range at address {:#X}", address)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(
+                    f,
+                    "ROM is {} bytes but at most {} bytes fit in memory",
+                    size, max
+                )
+            }
+            Chip8Error::RomUnreadable(err) => write!(f, "unable to read ROM: {}", err),
+            Chip8Error::TraceUnwritable(err) => write!(f, "unable to write trace: {}", err),
+        }
+    }
+}
+
+impl Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Chip8Error::RomUnreadable(err) | Chip8Error::TraceUnwritable(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(err: io::Error) -> Chip8Error {
+        Chip8Error::RomUnreadable(err)
+    }
+}
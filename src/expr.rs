@@ -0,0 +1,440 @@
+use crate::chip8::Chip8State;
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    /// Byte offset into the source where the error was found.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column + 1, self.message)
+    }
+}
+
+impl Error for ExprError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(u8),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// A parsed expression over the machine state, used for conditional breakpoints.
+///
+/// ```text
+/// V3 == 0x10 && I > 0x300
+/// [I + 2] != 0 || pc == 0x2A4
+/// ```
+///
+/// Operands are numbers (decimal, `0x` hex or `0b` binary), the registers `V0` to `VF`, `I`,
+/// `PC`, `SP`, the timers `DT` and `ST`, and memory bytes written `[address]`. Operators, from
+/// loosest to tightest binding, are `||`, `&&`, comparisons, `|`, `^`, `&`, `+` and `-`, and
+/// the unary `!` and `-`. Comparisons and logical operators give 1 or 0.
+///
+/// The source is kept for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, ExprError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end: source.len(),
+        };
+
+        let root = parser.parse_binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(ExprError {
+                column: token.column,
+                message: "unexpected input after expression".to_string(),
+            });
+        }
+
+        Ok(Expr {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn eval(&self, chip8: &Chip8State) -> i64 {
+        eval(&self.root, chip8)
+    }
+
+    /// Whether the expression is non-zero.
+    pub fn holds(&self, chip8: &Chip8State) -> bool {
+        self.eval(chip8) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn eval(node: &Node, chip8: &Chip8State) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Register(x) => chip8.registers[*x as usize] as i64,
+        Node::I => chip8.i_register as i64,
+        Node::Pc => chip8.pc as i64,
+        Node::Sp => chip8.sp as i64,
+        Node::Delay => chip8.delay_timer as i64,
+        Node::Sound => chip8.sound_timer as i64,
+        Node::Memory(address) => {
+            let address = eval(address, chip8);
+            if address < 0 {
+                return 0;
+            }
+            chip8.memory.get(address as usize).copied().unwrap_or(0) as i64
+        }
+        Node::Not(operand) => (eval(operand, chip8) == 0) as i64,
+        Node::Neg(operand) => eval(operand, chip8).wrapping_neg(),
+        Node::Binary(op, left, right) => {
+            let left = eval(left, chip8);
+            // Short-circuit so guards like `I < 0x1000 && [I] == 0` behave as expected.
+            match op {
+                BinaryOp::Or if left != 0 => return 1,
+                BinaryOp::And if left == 0 => return 0,
+                _ => (),
+            }
+            let right = eval(right, chip8);
+
+            match op {
+                BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                BinaryOp::Eq => (left == right) as i64,
+                BinaryOp::Ne => (left != right) as i64,
+                BinaryOp::Lt => (left < right) as i64,
+                BinaryOp::Le => (left <= right) as i64,
+                BinaryOp::Gt => (left > right) as i64,
+                BinaryOp::Ge => (left >= right) as i64,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Sub => left.wrapping_sub(right),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Number(i64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+// Longest first, so `<=` isn't read as `<` then `=`.
+const SYMBOLS: [&str; 17] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < source.len() {
+        let rest = &source[pos..];
+        let c = rest.chars().next().unwrap();
+
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+
+        let column = pos;
+        let kind = if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            pos += len;
+
+            if c.is_ascii_digit() {
+                TokenKind::Number(parse_number(word).ok_or_else(|| ExprError {
+                    column,
+                    message: format!("invalid number '{}'", word),
+                })?)
+            } else {
+                TokenKind::Ident(word.to_ascii_uppercase())
+            }
+        } else if c == ']' {
+            pos += 1;
+            TokenKind::Symbol("]")
+        } else {
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => {
+                    pos += symbol.len();
+                    TokenKind::Symbol(symbol)
+                }
+                None => {
+                    return Err(ExprError {
+                        column,
+                        message: format!("unexpected character '{}'", c),
+                    })
+                }
+            }
+        };
+
+        tokens.push(Token { kind, column });
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let lower = word.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+// Binary operators grouped by precedence, loosest first.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 7] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |token| token.column)
+    }
+
+    fn error(&self, message: &str) -> ExprError {
+        ExprError {
+            column: self.column(),
+            message: message.to_string(),
+        }
+    }
+
+    fn symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token {
+                kind: TokenKind::Symbol(symbol),
+                ..
+            }) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ExprError> {
+        if self.symbol() == Some(symbol) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", symbol)))
+        }
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Node, ExprError> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(&(_, op)) = PRECEDENCE[level]
+            .iter()
+            .find(|(symbol, _)| self.symbol() == Some(symbol))
+        {
+            self.pos += 1;
+            let right = self.parse_binary(level + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, ExprError> {
+        match self.symbol() {
+            Some("!") => {
+                self.pos += 1;
+                Ok(Node::Not(Box::new(self.parse_unary()?)))
+            }
+            Some("-") => {
+                self.pos += 1;
+                Ok(Node::Neg(Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, ExprError> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => token,
+            None => return Err(self.error("expected a value")),
+        };
+
+        let node = match &token.kind {
+            TokenKind::Number(value) => Node::Number(*value),
+            TokenKind::Ident(name) => match name.as_str() {
+                "I" => Node::I,
+                "PC" => Node::Pc,
+                "SP" => Node::Sp,
+                "DT" => Node::Delay,
+                "ST" => Node::Sound,
+                _ => match register(name) {
+                    Some(x) => Node::Register(x),
+                    None => return Err(self.error(&format!("unknown name '{}'", name))),
+                },
+            },
+            TokenKind::Symbol("(") => {
+                self.pos += 1;
+                let node = self.parse_binary(0)?;
+                self.expect(")")?;
+                return Ok(node);
+            }
+            TokenKind::Symbol("[") => {
+                self.pos += 1;
+                let address = self.parse_binary(0)?;
+                self.expect("]")?;
+                return Ok(Node::Memory(Box::new(address)));
+            }
+            TokenKind::Symbol(_) => return Err(self.error("expected a value")),
+        };
+
+        self.pos += 1;
+        Ok(node)
+    }
+}
+
+fn register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix('V')?;
+    if digit.len() == 1 {
+        u8::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, chip8: &Chip8State) -> i64 {
+        Expr::parse(source).unwrap().eval(chip8)
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        let err = Expr::parse(source).unwrap_err();
+        (err.column, err.message)
+    }
+
+    #[test]
+    fn precedence() {
+        let chip8 = Chip8State::new();
+
+        assert_eq!(eval("1 + 2 & 3", &chip8), 3);
+        assert_eq!(eval("1 | 2 ^ 3 & 6", &chip8), 1);
+        assert_eq!(eval("4 - 1 - 1", &chip8), 2);
+        assert_eq!(eval("-1 + 3", &chip8), 2);
+        assert_eq!(eval("!0 + 1", &chip8), 2);
+        assert_eq!(eval("0 && 1 || 1", &chip8), 1);
+        assert_eq!(eval("1 < 2 == 1", &chip8), 1);
+    }
+
+    #[test]
+    fn operands() {
+        let mut chip8 = Chip8State::new();
+        chip8.registers[3] = 0x10;
+        chip8.registers[0xF] = 1;
+        chip8.i_register = 0x300;
+        chip8.memory[0x302] = 7;
+        chip8.delay_timer = 9;
+
+        assert!(Expr::parse("V3 == 0x10 && [I + 2] == 7")
+            .unwrap()
+            .holds(&chip8));
+        assert_eq!(eval("vf + v3", &chip8), 0x11);
+        assert_eq!(eval("pc", &chip8), 0x200);
+        assert_eq!(eval("DT - ST + SP", &chip8), 9);
+        assert_eq!(eval("0b101", &chip8), 5);
+        assert_eq!(eval("[0x10000] + [-1]", &chip8), 0);
+        // The right side isn't evaluated once the left decides the result.
+        assert_eq!(eval("I < 0x1000 || [I] == 1", &chip8), 1);
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("V3 =="), (5, "expected a value".to_string()));
+        assert_eq!(error("V3 $ 1"), (3, "unexpected character '$'".to_string()));
+        assert_eq!(error("1 + VG"), (4, "unknown name 'VG'".to_string()));
+        assert_eq!(error("[I + 1"), (6, "expected ']'".to_string()));
+        assert_eq!(
+            error("1 2"),
+            (2, "unexpected input after expression".to_string())
+        );
+        assert_eq!(error("0xZZ"), (0, "invalid number '0xZZ'".to_string()));
+        assert_eq!(
+            Expr::parse("(1").unwrap_err().to_string(),
+            "column 3: expected ')'"
+        );
+    }
+}
@@ -0,0 +1,570 @@
+use crate::chip8::Chip8State;
+use crate::debugger::Debugger;
+
+use std::io;
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+pub const SIGINT: u8 = 2;
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+
+// V0-VF, I, PC and SP.
+const REGISTER_COUNT: usize = 19;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// A GDB remote serial protocol server for one client at a time.
+///
+/// Registers are numbered V0-VF (0-15), I (16), PC (17) and SP (18), with the 16-bit ones sent
+/// big-endian like the rest of CHIP-8. Execution control goes through a `Debugger`, so its
+/// `run_frame` must be called while the client has the target running.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    acks: bool,
+    // The client continued and is waiting to hear why the target stopped.
+    running: bool,
+}
+
+impl GdbServer {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbServer {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Accept a client and handle any packets it has sent, without blocking. Call this
+    /// regularly, e.g. once per frame.
+    ///
+    /// A newly attached client finds the target paused.
+    pub fn poll(&mut self, chip8: &mut Chip8State, debugger: &mut Debugger) -> io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        acks: true,
+                        running: false,
+                    });
+                    debugger.pause();
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let client = self.client.as_mut().unwrap();
+        let keep = match client.receive() {
+            Ok(true) => client.handle_packets(chip8, debugger),
+            Ok(false) => Ok(false),
+            Err(err) => Err(err),
+        };
+
+        match keep {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.client = None;
+                Ok(())
+            }
+            Err(err) => {
+                self.client = None;
+                Err(err)
+            }
+        }
+    }
+
+    /// Tell a client waiting on `c` that the target stopped with `signal`.
+    pub fn report_stop(&mut self, signal: u8) -> io::Result<()> {
+        match self.client.as_mut() {
+            Some(client) if client.running => {
+                client.running = false;
+                client.send(&format!("S{:02x}", signal))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Serve a single client until it detaches, running emulation whenever it asks.
+    pub fn serve(&mut self, chip8: &mut Chip8State, debugger: &mut Debugger) -> io::Result<()> {
+        let mut attached = false;
+
+        loop {
+            self.poll(chip8, debugger)?;
+            if attached && !self.is_connected() {
+                return Ok(());
+            }
+            attached |= self.is_connected();
+
+            if debugger.is_paused() {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+
+            match debugger.run_frame(chip8) {
+                Ok(Some(_)) => self.report_stop(SIGTRAP)?,
+                Ok(None) => (),
+                Err(_) => {
+                    debugger.pause();
+                    self.report_stop(SIGILL)?;
+                }
+            }
+        }
+    }
+}
+
+impl Client {
+    // Read whatever has arrived. Returns `false` once the client has disconnected.
+    fn receive(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn handle_packets(
+        &mut self,
+        chip8: &mut Chip8State,
+        debugger: &mut Debugger,
+    ) -> io::Result<bool> {
+        loop {
+            let start = match self.buffer.iter().position(|&b| b == b'$' || b == 0x03) {
+                Some(start) => start,
+                None => {
+                    // Only acknowledgements, which need no reply.
+                    self.buffer.clear();
+                    return Ok(true);
+                }
+            };
+
+            if self.buffer[start] == 0x03 {
+                self.buffer.drain(..=start);
+                debugger.pause();
+                self.running = false;
+                self.send(&format!("S{:02x}", SIGINT))?;
+                continue;
+            }
+
+            let end = match self.buffer[start..].iter().position(|&b| b == b'#') {
+                Some(end) if start + end + 2 < self.buffer.len() => start + end,
+                _ => return Ok(true),
+            };
+            let packet = String::from_utf8_lossy(&self.buffer[start + 1..end]).into_owned();
+            let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            self.buffer.drain(..end + 3);
+
+            if self.acks {
+                let sum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+                if checksum != Some(sum) {
+                    self.write(b"-")?;
+                    continue;
+                }
+                self.write(b"+")?;
+            }
+
+            if !self.handle(&packet, chip8, debugger)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Handle one packet. Returns `false` if the client has detached.
+    fn handle(
+        &mut self,
+        packet: &str,
+        chip8: &mut Chip8State,
+        debugger: &mut Debugger,
+    ) -> io::Result<bool> {
+        let command = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+
+        match command {
+            "?" => self.send(&format!("S{:02x}", SIGTRAP))?,
+            "g" => {
+                let registers: String = (0..REGISTER_COUNT)
+                    .map(|n| read_register(chip8, n).unwrap())
+                    .collect();
+                self.send(&registers)?;
+            }
+            "G" => {
+                let mut rest = args;
+                for n in 0..REGISTER_COUNT {
+                    let width = register_width(n) * 2;
+                    if rest.len() < width {
+                        break;
+                    }
+                    write_register(chip8, n, &rest[..width]);
+                    rest = &rest[width..];
+                }
+                self.send("OK")?;
+            }
+            "p" => match usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| read_register(chip8, n))
+            {
+                Some(value) => self.send(&value)?,
+                None => self.send("E01")?,
+            },
+            "P" => {
+                let written = args.find('=').and_then(|eq| {
+                    let n = usize::from_str_radix(&args[..eq], 16).ok()?;
+                    write_register(chip8, n, &args[eq + 1..])
+                });
+                self.send(if written.is_some() { "OK" } else { "E01" })?;
+            }
+            "m" => match parse_range(args).and_then(|(address, length)| {
+                chip8.memory.get(address..address.checked_add(length)?)
+            }) {
+                Some(bytes) => self.send(&to_hex(bytes))?,
+                None => self.send("E01")?,
+            },
+            "M" => {
+                let written = args.find(':').and_then(|colon| {
+                    let (address, length) = parse_range(&args[..colon])?;
+                    let bytes = from_hex(&args[colon + 1..])?;
+                    if bytes.len() != length {
+                        return None;
+                    }
+                    chip8
+                        .memory
+                        .get_mut(address..address.checked_add(length)?)?
+                        .copy_from_slice(&bytes);
+                    Some(())
+                });
+                self.send(if written.is_some() { "OK" } else { "E01" })?;
+            }
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let address = fields
+                    .next()
+                    .and_then(|address| u16::from_str_radix(address, 16).ok());
+
+                match (kind, address) {
+                    (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                        if command == "Z" {
+                            debugger.add_breakpoint(address);
+                        } else {
+                            debugger.remove_breakpoint(address);
+                        }
+                        self.send("OK")?;
+                    }
+                    (Some(_), Some(_)) => self.send("")?,
+                    _ => self.send("E01")?,
+                }
+            }
+            "s" => {
+                let signal = match debugger.step(chip8) {
+                    Ok(()) => SIGTRAP,
+                    Err(_) => SIGILL,
+                };
+                self.send(&format!("S{:02x}", signal))?;
+            }
+            "c" => {
+                debugger.resume();
+                self.running = true;
+            }
+            "D" => {
+                self.send("OK")?;
+                debugger.resume();
+                return Ok(false);
+            }
+            "k" => return Ok(false),
+            "H" => self.send("OK")?,
+            "q" | "Q" => self.handle_query(packet)?,
+            _ => self.send("")?,
+        }
+
+        Ok(true)
+    }
+
+    fn handle_query(&mut self, packet: &str) -> io::Result<()> {
+        const TARGET_XML_QUERY: &str = "qXfer:features:read:target.xml:";
+
+        if packet.starts_with("qSupported") {
+            self.send("PacketSize=4000;QStartNoAckMode+;qXfer:features:read+")
+        } else if packet == "QStartNoAckMode" {
+            self.send("OK")?;
+            self.acks = false;
+            Ok(())
+        } else if packet == "qAttached" {
+            self.send("1")
+        } else if packet == "qC" {
+            self.send("QC1")
+        } else if packet == "qfThreadInfo" {
+            self.send("m1")
+        } else if packet == "qsThreadInfo" {
+            self.send("l")
+        } else if let Some(range) = packet.strip_prefix(TARGET_XML_QUERY) {
+            match parse_range(range) {
+                Some((offset, length)) => {
+                    let start = offset.min(TARGET_XML.len());
+                    let end = start.saturating_add(length).min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { "m" } else { "l" };
+                    self.send(&format!("{}{}", more, &TARGET_XML[start..end]))
+                }
+                None => self.send("E01"),
+            }
+        } else {
+            self.send("")
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+
+        self.write(packet.as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(bytes);
+        self.stream.set_nonblocking(true)?;
+
+        result
+    }
+}
+
+fn register_width(n: usize) -> usize {
+    match n {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(chip8: &Chip8State, n: usize) -> Option<String> {
+    let value = match n {
+        0..=15 => return Some(format!("{:02x}", chip8.registers[n])),
+        16 => chip8.i_register,
+        17 => chip8.pc,
+        18 => return Some(format!("{:02x}", chip8.sp)),
+        _ => return None,
+    };
+
+    Some(format!("{:04x}", value))
+}
+
+fn write_register(chip8: &mut Chip8State, n: usize, hex: &str) -> Option<()> {
+    let bytes = from_hex(hex)?;
+    if bytes.len() != register_width(n) {
+        return None;
+    }
+
+    match n {
+        0..=15 => chip8.registers[n] = bytes[0],
+        16 => chip8.i_register = (bytes[0] as u16) << 8 | bytes[1] as u16,
+        17 => chip8.pc = (bytes[0] as u16) << 8 | bytes[1] as u16,
+        18 => chip8.sp = bytes[0].min(chip8.stack.len() as u8),
+        _ => return None,
+    }
+
+    Some(())
+}
+
+// `ADDRESS,LENGTH` in hex.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let comma = range.find(',')?;
+    let address = usize::from_str_radix(&range[..comma], 16).ok()?;
+    let length = usize::from_str_radix(&range[comma + 1..], 16).ok()?;
+
+    Some((address, length))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some((hex_digit(*high)? << 4) | hex_digit(*low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A server and a client talking to it over a local socket, all on one thread.
+    struct Session {
+        server: GdbServer,
+        client: TcpStream,
+        received: Vec<u8>,
+        chip8: Chip8State,
+        debugger: Debugger,
+    }
+
+    impl Session {
+        fn new(rom: &[u8]) -> Session {
+            let server = GdbServer::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+            client.set_nonblocking(true).unwrap();
+
+            Session {
+                server,
+                client,
+                received: Vec::new(),
+                chip8: Chip8State::with_rom(rom).unwrap(),
+                debugger: Debugger::new(),
+            }
+        }
+
+        fn request(&mut self, packet: &str) -> String {
+            let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            write!(self.client, "${}#{:02x}", packet, checksum).unwrap();
+
+            self.reply()
+        }
+
+        // Poll the server, running emulation while the target runs, until a reply arrives.
+        fn reply(&mut self) -> String {
+            for _ in 0..1000 {
+                self.server
+                    .poll(&mut self.chip8, &mut self.debugger)
+                    .unwrap();
+                if !self.debugger.is_paused()
+                    && self.debugger.run_frame(&mut self.chip8).unwrap().is_some()
+                {
+                    self.server.report_stop(SIGTRAP).unwrap();
+                }
+
+                let mut chunk = [0; 4096];
+                match self.client.read(&mut chunk) {
+                    Ok(count) => self.received.extend_from_slice(&chunk[..count]),
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(err) => panic!("{}", err),
+                }
+                if let Some(reply) = self.take_packet() {
+                    return reply;
+                }
+
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            panic!("no reply");
+        }
+
+        fn take_packet(&mut self) -> Option<String> {
+            let start = self.received.iter().position(|&b| b == b'$')?;
+            let end = self.received.iter().position(|&b| b == b'#')?;
+            if end + 2 >= self.received.len() {
+                return None;
+            }
+
+            // The request was acknowledged before the reply.
+            assert_eq!(&self.received[..start], b"+");
+            let data = String::from_utf8(self.received[start + 1..end].to_vec()).unwrap();
+            let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            assert_eq!(
+                &self.received[end + 1..end + 3],
+                format!("{:02x}", sum).as_bytes()
+            );
+            self.received.drain(..end + 3);
+
+            Some(data)
+        }
+    }
+
+    #[test]
+    fn serves_a_client() {
+        // LD V0, 5; ADD V0, 1; JP 0x202
+        let mut session = Session::new(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+
+        assert_eq!(session.request("?"), "S05");
+        assert!(session.debugger.is_paused());
+        assert_eq!(
+            session.request("g"),
+            format!("{}{}{}{}", "00".repeat(16), "0000", "0200", "00")
+        );
+        assert_eq!(session.request("m200,4"), "60057001");
+        assert_eq!(session.request("mffff,ffffffffffffffff"), "E01");
+
+        assert_eq!(session.request("s"), "S05");
+        assert_eq!(session.request("p0"), "05");
+        assert_eq!(session.request("p11"), "0202");
+
+        // Running stops before the instruction at a breakpoint.
+        assert_eq!(session.request("Z0,204"), "OK");
+        assert_eq!(session.request("c"), "S05");
+        assert_eq!(session.chip8.pc, 0x204);
+        assert_eq!(session.chip8.registers[0], 6);
+
+        assert_eq!(session.request("z0,204"), "OK");
+        assert_eq!(session.request("D"), "OK");
+    }
+
+    #[test]
+    fn target_xml_reads_are_clamped() {
+        let mut session = Session::new(&[0x12, 0x00]);
+
+        let reply = session.request("qXfer:features:read:target.xml:0,ffffffffffffffff");
+        assert_eq!(reply, format!("l{}", TARGET_XML));
+
+        let reply = session.request("qXfer:features:read:target.xml:ffffffffffffffff,10");
+        assert_eq!(reply, "l");
+    }
+}
@@ -0,0 +1,310 @@
+use crate::chip8::Chip8State;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::movie::{Movie, Player};
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeyScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeyScriptError {}
+
+/// A key pressed or released at the start of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Parse a key script, with one `FRAME KEY down|up` event per line and the key in hex.
+///
+/// ```text
+/// # Start the game, then hold 6 for a second.
+/// 60 5 down
+/// 62 5 up
+/// 120 6 down
+/// 180 6 up
+/// ```
+///
+/// Blank lines and anything after `#` are ignored. Events are returned in frame order.
+pub fn parse_key_script(source: &str) -> Result<Vec<KeyEvent>, KeyScriptError> {
+    let mut events = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+
+        let error = |message: String| KeyScriptError {
+            line: number + 1,
+            message,
+        };
+        if fields.len() != 3 {
+            return Err(error("expected FRAME KEY down|up".to_string()));
+        }
+
+        let frame = fields[0]
+            .parse()
+            .map_err(|_| error(format!("invalid frame '{}'", fields[0])))?;
+        let key = u8::from_str_radix(fields[1], 16)
+            .ok()
+            .filter(|key| *key < 16)
+            .ok_or_else(|| error(format!("invalid key '{}'", fields[1])))?;
+        let pressed = match fields[2].to_ascii_lowercase().as_str() {
+            "down" | "press" => true,
+            "up" | "release" => false,
+            other => return Err(error(format!("expected down or up, found '{}'", other))),
+        };
+
+        events.push(KeyEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+
+    // Stable, so events on the same frame keep their order.
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+/// How long to run for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Frames(u64),
+    Cycles(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub limit: Limit,
+    pub keys: Vec<KeyEvent>,
+    /// Stop at a `1NNN` that jumps to itself, which is how most test ROMs finish.
+    pub stop_on_halt_loop: bool,
+    /// A movie to replay key presses from, cycle by cycle. The machine should already be set
+    /// up with `Movie::prepare`.
+    pub movie: Option<Movie>,
+}
+
+#[derive(Debug)]
+pub enum Stop {
+    /// Ran for the whole limit.
+    Limit,
+    HaltLoop(u16),
+    /// `00FD` was executed.
+    Exit,
+    Error(Chip8Error),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Limit => write!(f, "reached the limit"),
+            Stop::HaltLoop(address) => write!(f, "halt loop at {:#05X}", address),
+            Stop::Exit => write!(f, "exited"),
+            Stop::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub stop: Stop,
+    /// Whole frames run.
+    pub frames: u64,
+    pub cycles: u64,
+}
+
+/// Run `chip8` without a window until the limit, applying scripted key events at the start
+/// of each frame.
+pub fn run(chip8: &mut Chip8State, config: &Config) -> Report {
+    run_with(chip8, config, |_| ())
+}
+
+/// Like `run`, calling `on_frame` with the machine at the end of each whole frame.
+pub fn run_with<F>(chip8: &mut Chip8State, config: &Config, mut on_frame: F) -> Report
+where
+    F: FnMut(&Chip8State),
+{
+    let start = chip8.cycles;
+    let mut frames = 0;
+    let mut events = config.keys.iter().peekable();
+    let mut player = config.movie.as_ref().map(Player::new);
+
+    let stop = 'frames: loop {
+        if config.limit == Limit::Frames(frames) {
+            break Stop::Limit;
+        }
+
+        while let Some(event) = events.next_if(|event| event.frame <= frames) {
+            chip8.keys[event.key as usize] = event.pressed;
+        }
+
+        loop {
+            if let Limit::Cycles(cycles) = config.limit {
+                if chip8.cycles - start >= cycles {
+                    break 'frames Stop::Limit;
+                }
+            }
+            if chip8.halted {
+                break 'frames Stop::Exit;
+            }
+            if config.stop_on_halt_loop
+                && chip8.instruction_at(chip8.pc) == Some(Instruction::Jp(chip8.pc))
+            {
+                break 'frames Stop::HaltLoop(chip8.pc);
+            }
+
+            if let Some(player) = player.as_mut() {
+                player.apply(chip8);
+            }
+            match chip8.step() {
+                Ok(true) => break,
+                Ok(false) => (),
+                Err(err) => break 'frames Stop::Error(err),
+            }
+        }
+
+        frames += 1;
+        on_frame(chip8);
+    };
+
+    Report {
+        stop,
+        frames,
+        cycles: chip8.cycles - start,
+    }
+}
+
+/// The visible screen as text, one line per row. Unlit pixels are `.` and lit ones `#`, or
+/// the pixel value when XO-CHIP draws to the second plane.
+pub fn screen_text(chip8: &Chip8State) -> String {
+    let width = chip8.screen_width();
+    let mut text = String::with_capacity((width + 1) * chip8.screen_height());
+
+    for row in &chip8.video_buf[..chip8.screen_height()] {
+        for &pixel in &row[..width] {
+            text.push(match pixel {
+                0 => '.',
+                1 => '#',
+                _ => (b'0' + pixel) as char,
+            });
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 1; JP 0x202
+    const HALT: [u8; 4] = [0x60, 0x01, 0x12, 0x02];
+
+    fn limited(limit: Limit) -> Config {
+        Config {
+            limit,
+            keys: Vec::new(),
+            stop_on_halt_loop: false,
+            movie: None,
+        }
+    }
+
+    #[test]
+    fn key_scripts() {
+        let script = "# comment\n\n120 f up\n 60 A down # hold A\n60 0 press\n";
+        let event = |frame, key, pressed| KeyEvent {
+            frame,
+            key,
+            pressed,
+        };
+
+        assert_eq!(
+            parse_key_script(script).unwrap(),
+            [
+                event(60, 0xA, true),
+                event(60, 0, true),
+                event(120, 0xF, false)
+            ]
+        );
+
+        let error = |script| parse_key_script(script).unwrap_err().to_string();
+        assert_eq!(error("1 2"), "line 1: expected FRAME KEY down|up");
+        assert_eq!(error("\nx 2 up"), "line 2: invalid frame 'x'");
+        assert_eq!(error("1 10 up"), "line 1: invalid key '10'");
+        assert_eq!(
+            error("1 2 sideways"),
+            "line 1: expected down or up, found 'sideways'"
+        );
+    }
+
+    #[test]
+    fn frame_and_cycle_limits() {
+        let mut chip8 = Chip8State::with_rom(&HALT).unwrap();
+        let mut seen = 0;
+        let mut config = limited(Limit::Frames(3));
+        config.keys = parse_key_script("2 A down\n5 B down").unwrap();
+
+        let report = run_with(&mut chip8, &config, |_| seen += 1);
+        assert!(matches!(report.stop, Stop::Limit));
+        assert_eq!((report.frames, seen), (3, 3));
+        assert!(chip8.keys[0xA] && !chip8.keys[0xB]);
+
+        let mut chip8 = Chip8State::with_rom(&HALT).unwrap();
+        let report = run(&mut chip8, &limited(Limit::Cycles(25)));
+        assert!(matches!(report.stop, Stop::Limit));
+        assert_eq!(report.cycles, 25);
+    }
+
+    #[test]
+    fn stops_at_halt_loops_and_exit() {
+        let mut chip8 = Chip8State::with_rom(&HALT).unwrap();
+        let mut config = limited(Limit::Frames(60));
+        config.stop_on_halt_loop = true;
+
+        let report = run(&mut chip8, &config);
+        assert!(matches!(report.stop, Stop::HaltLoop(0x202)));
+        assert_eq!((report.frames, report.cycles), (0, 1));
+
+        // LD V0, 1; EXIT
+        let mut chip8 = Chip8State::with_rom(&[0x60, 0x01, 0x00, 0xFD]).unwrap();
+        let report = run(&mut chip8, &config);
+        assert!(matches!(report.stop, Stop::Exit));
+        assert_eq!(report.cycles, 2);
+    }
+
+    #[test]
+    fn screen_as_text() {
+        // LD V0, 0; LD F, V0; DRW V0, V0, 5; JP 0x206
+        let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+        let mut chip8 = Chip8State::with_rom(&rom).unwrap();
+        let mut config = limited(Limit::Frames(60));
+        config.stop_on_halt_loop = true;
+        run(&mut chip8, &config);
+
+        let text = screen_text(&chip8);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|line| line.len() == 64));
+        assert!(lines[0].starts_with("####."));
+        assert!(lines[1].starts_with("#..#."));
+        assert!(lines[4].starts_with("####."));
+        assert!(!lines[5].contains('#'));
+    }
+}
@@ -0,0 +1,474 @@
+use std::fmt;
+
+/// The first word of XO-CHIP's four byte `F000 NNNN` instruction.
+pub const LONG_PREFIX: u16 = 0xF000;
+
+/// A single decoded CHIP-8 instruction.
+///
+/// `x` and `y` are register indices, `nn` is an immediate byte and `n` an immediate nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(u16),
+    Cls,
+    Ret,
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    SeImm { x: u8, nn: u8 },
+    SneImm { x: u8, nn: u8 },
+    SeReg { x: u8, y: u8 },
+    SaveRange { x: u8, y: u8 },
+    LoadRange { x: u8, y: u8 },
+    LdImm { x: u8, nn: u8 },
+    AddImm { x: u8, nn: u8 },
+    LdReg { x: u8, y: u8 },
+    Or { x: u8, y: u8 },
+    And { x: u8, y: u8 },
+    Xor { x: u8, y: u8 },
+    AddReg { x: u8, y: u8 },
+    Sub { x: u8, y: u8 },
+    Shr { x: u8, y: u8 },
+    Subn { x: u8, y: u8 },
+    Shl { x: u8, y: u8 },
+    SneReg { x: u8, y: u8 },
+    LdI(u16),
+    LdILong(u16),
+    JpV0(u16),
+    Rnd { x: u8, nn: u8 },
+    Drw { x: u8, y: u8, n: u8 },
+    Skp(u8),
+    Sknp(u8),
+    Plane(u8),
+    Audio,
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    Pitch(u8),
+    LdFVx(u8),
+    LdHfVx(u8),
+    LdBVx(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+}
+
+impl Instruction {
+    /// Decode an opcode, returning `None` if it isn't a valid instruction.
+    ///
+    /// `F000` needs the word that follows it, so only `decode_pair` decodes it.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let nibble_one = (opcode & 0xF000) >> 12;
+        let nibble_two = (opcode & 0x0F00) >> 8;
+        let nibble_three = (opcode & 0x00F0) >> 4;
+        let nibble_four = opcode & 0x000F;
+
+        let x = nibble_two as u8;
+        let y = nibble_three as u8;
+        let n = nibble_four as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let address = opcode & 0x0FFF;
+
+        let instruction = match (nibble_one, nibble_two, nibble_three, nibble_four) {
+            (0, 0, 0xE, 0) => Instruction::Cls,
+            (0, 0, 0xE, 0xE) => Instruction::Ret,
+            (0, 0, 0xC, _) => Instruction::ScrollDown(n),
+            (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0, 0, 0xF, 0xD) => Instruction::Exit,
+            (0, 0, 0xF, 0xE) => Instruction::Low,
+            (0, 0, 0xF, 0xF) => Instruction::High,
+            (0, _, _, _) => Instruction::Sys(address),
+            (1, _, _, _) => Instruction::Jp(address),
+            (2, _, _, _) => Instruction::Call(address),
+            (3, _, _, _) => Instruction::SeImm { x, nn },
+            (4, _, _, _) => Instruction::SneImm { x, nn },
+            (5, _, _, 0) => Instruction::SeReg { x, y },
+            (5, _, _, 2) => Instruction::SaveRange { x, y },
+            (5, _, _, 3) => Instruction::LoadRange { x, y },
+            (6, _, _, _) => Instruction::LdImm { x, nn },
+            (7, _, _, _) => Instruction::AddImm { x, nn },
+            (8, _, _, 0) => Instruction::LdReg { x, y },
+            (8, _, _, 1) => Instruction::Or { x, y },
+            (8, _, _, 2) => Instruction::And { x, y },
+            (8, _, _, 3) => Instruction::Xor { x, y },
+            (8, _, _, 4) => Instruction::AddReg { x, y },
+            (8, _, _, 5) => Instruction::Sub { x, y },
+            (8, _, _, 6) => Instruction::Shr { x, y },
+            (8, _, _, 7) => Instruction::Subn { x, y },
+            (8, _, _, 0xE) => Instruction::Shl { x, y },
+            (9, _, _, 0) => Instruction::SneReg { x, y },
+            (0xA, _, _, _) => Instruction::LdI(address),
+            (0xB, _, _, _) => Instruction::JpV0(address),
+            (0xC, _, _, _) => Instruction::Rnd { x, nn },
+            (0xD, _, _, _) => Instruction::Drw { x, y, n },
+            (0xE, _, 9, 0xE) => Instruction::Skp(x),
+            (0xE, _, 0xA, 1) => Instruction::Sknp(x),
+            (0xF, 0, 0, 2) => Instruction::Audio,
+            (0xF, _, 0, 1) => Instruction::Plane(x),
+            (0xF, _, 0, 7) => Instruction::LdVxDt(x),
+            (0xF, _, 0, 0xA) => Instruction::LdVxK(x),
+            (0xF, _, 1, 5) => Instruction::LdDtVx(x),
+            (0xF, _, 1, 8) => Instruction::LdStVx(x),
+            (0xF, _, 1, 0xE) => Instruction::AddIVx(x),
+            (0xF, _, 3, 0xA) => Instruction::Pitch(x),
+            (0xF, _, 2, 9) => Instruction::LdFVx(x),
+            (0xF, _, 3, 0) => Instruction::LdHfVx(x),
+            (0xF, _, 3, 3) => Instruction::LdBVx(x),
+            (0xF, _, 5, 5) => Instruction::LdIVx(x),
+            (0xF, _, 6, 5) => Instruction::LdVxI(x),
+            (0xF, _, 7, 5) => Instruction::LdRVx(x),
+            (0xF, _, 8, 5) => Instruction::LdVxR(x),
+            _ => return None,
+        };
+
+        Some(instruction)
+    }
+
+    /// Decode an opcode along with the word that follows it in memory.
+    pub fn decode_pair(opcode: u16, next: u16) -> Option<Instruction> {
+        if opcode == LONG_PREFIX {
+            Some(Instruction::LdILong(next))
+        } else {
+            Instruction::decode(opcode)
+        }
+    }
+
+    /// The size of the instruction in bytes.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Encode the instruction into the bytes it occupies in memory.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LdILong(address) = self {
+            bytes.extend_from_slice(&address.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Encode the instruction back into its opcode.
+    ///
+    /// For `LdILong` this is only the first word; use `to_bytes` to include the address.
+    pub fn encode(&self) -> u16 {
+        let xy = |high: u16, x: u8, y: u8, low: u16| {
+            high << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | low
+        };
+        let xnn = |high: u16, x: u8, nn: u8| high << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+        let addr = |high: u16, address: u16| high << 12 | (address & 0x0FFF);
+
+        match *self {
+            Instruction::Sys(address) => addr(0, address),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(address) => addr(1, address),
+            Instruction::Call(address) => addr(2, address),
+            Instruction::SeImm { x, nn } => xnn(3, x, nn),
+            Instruction::SneImm { x, nn } => xnn(4, x, nn),
+            Instruction::SeReg { x, y } => xy(5, x, y, 0),
+            Instruction::SaveRange { x, y } => xy(5, x, y, 2),
+            Instruction::LoadRange { x, y } => xy(5, x, y, 3),
+            Instruction::LdImm { x, nn } => xnn(6, x, nn),
+            Instruction::AddImm { x, nn } => xnn(7, x, nn),
+            Instruction::LdReg { x, y } => xy(8, x, y, 0),
+            Instruction::Or { x, y } => xy(8, x, y, 1),
+            Instruction::And { x, y } => xy(8, x, y, 2),
+            Instruction::Xor { x, y } => xy(8, x, y, 3),
+            Instruction::AddReg { x, y } => xy(8, x, y, 4),
+            Instruction::Sub { x, y } => xy(8, x, y, 5),
+            Instruction::Shr { x, y } => xy(8, x, y, 6),
+            Instruction::Subn { x, y } => xy(8, x, y, 7),
+            Instruction::Shl { x, y } => xy(8, x, y, 0xE),
+            Instruction::SneReg { x, y } => xy(9, x, y, 0),
+            Instruction::LdI(address) => addr(0xA, address),
+            Instruction::LdILong(_) => LONG_PREFIX,
+            Instruction::JpV0(address) => addr(0xB, address),
+            Instruction::Rnd { x, nn } => xnn(0xC, x, nn),
+            Instruction::Drw { x, y, n } => xy(0xD, x, y, n as u16 & 0xF),
+            Instruction::Skp(x) => xnn(0xE, x, 0x9E),
+            Instruction::Sknp(x) => xnn(0xE, x, 0xA1),
+            Instruction::Plane(n) => xnn(0xF, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt(x) => xnn(0xF, x, 0x07),
+            Instruction::LdVxK(x) => xnn(0xF, x, 0x0A),
+            Instruction::LdDtVx(x) => xnn(0xF, x, 0x15),
+            Instruction::LdStVx(x) => xnn(0xF, x, 0x18),
+            Instruction::AddIVx(x) => xnn(0xF, x, 0x1E),
+            Instruction::Pitch(x) => xnn(0xF, x, 0x3A),
+            Instruction::LdFVx(x) => xnn(0xF, x, 0x29),
+            Instruction::LdHfVx(x) => xnn(0xF, x, 0x30),
+            Instruction::LdBVx(x) => xnn(0xF, x, 0x33),
+            Instruction::LdIVx(x) => xnn(0xF, x, 0x55),
+            Instruction::LdVxI(x) => xnn(0xF, x, 0x65),
+            Instruction::LdRVx(x) => xnn(0xF, x, 0x75),
+            Instruction::LdVxR(x) => xnn(0xF, x, 0x85),
+        }
+    }
+
+    /// A plain English description of what the instruction does.
+    pub fn describe(&self) -> String {
+        match *self {
+            Instruction::Sys(address) => format!(
+                "Execute machine language subroutine at address {:#05X}",
+                address
+            ),
+            Instruction::Cls => "Clear the screen".to_string(),
+            Instruction::Ret => "Return from a subroutine".to_string(),
+            Instruction::ScrollDown(n) => format!("Scroll the display down by {} pixels", n),
+            Instruction::ScrollRight => "Scroll the display right by 4 pixels".to_string(),
+            Instruction::ScrollLeft => "Scroll the display left by 4 pixels".to_string(),
+            Instruction::Exit => "Exit the interpreter".to_string(),
+            Instruction::Low => "Switch to 64x32 low resolution mode".to_string(),
+            Instruction::High => "Switch to 128x64 high resolution mode".to_string(),
+            Instruction::Jp(address) => format!("Jump to address {:#05X}", address),
+            Instruction::Call(address) => {
+                format!("Execute subroutine starting at address {:#05X}", address)
+            }
+            Instruction::SeImm { x, nn } => format!(
+                "Skip the following instruction if the value of register V{:X} equals {:#04X}",
+                x, nn
+            ),
+            Instruction::SneImm { x, nn } => format!("Skip the following instruction if the value of register V{:X} is not equal to {:#04X}", x, nn),
+            Instruction::SeReg { x, y } => format!("Skip the following instruction if the value of register V{:X} is equal to the value of register V{:X}", x, y),
+            Instruction::SaveRange { x, y } => format!(
+                "Store the values of registers V{:X} to V{:X} inclusive in memory starting at address I",
+                x, y
+            ),
+            Instruction::LoadRange { x, y } => format!(
+                "Fill registers V{:X} to V{:X} inclusive with the values stored in memory starting at address I",
+                x, y
+            ),
+            Instruction::LdImm { x, nn } => {
+                format!("Store number {:#04X} in register V{:X}", nn, x)
+            }
+            Instruction::AddImm { x, nn } => {
+                format!("Add the value {:#04X} to register V{:X}", nn, x)
+            }
+            Instruction::LdReg { x, y } => {
+                format!("Store the value of register V{:X} in register V{:X}", y, x)
+            }
+            Instruction::Or { x, y } => format!("Set V{0:X} to V{0:X} OR V{1:X}", x, y),
+            Instruction::And { x, y } => format!("Set V{0:X} to V{0:X} AND V{1:X}", x, y),
+            Instruction::Xor { x, y } => format!("Set V{0:X} to V{0:X} XOR V{1:X}", x, y),
+            Instruction::AddReg { x, y } => format!(
+                "Add the value of register V{:X} to register V{:X}\n\
+                 Set VF to 01 if a carry occurs\n\
+                 Set VF to 00 if a carry does not occur",
+                y, x
+            ),
+            Instruction::Sub { x, y } => format!(
+                "Subtract the value of register V{:X} from register V{:X}\n\
+                 Set VF to 00 if a borrow occurs\n\
+                 Set VF to 01 if a borrow does not occur",
+                y, x
+            ),
+            Instruction::Shr { x, y } => format!(
+                "Store the value of register V{:X} shifted right one bit in register V{:X}\n\
+                 Set register VF to the least significant bit prior to the shift",
+                y, x
+            ),
+            Instruction::Subn { x, y } => format!(
+                "Set register V{0:X} to the value of V{1:X} minus V{0:X}\n\
+                 Set VF to 00 if a borrow occurs\n\
+                 Set VF to 01 if a borrow does not occur",
+                x, y
+            ),
+            Instruction::Shl { x, y } => format!(
+                "Store the value of register V{:X} shifted left one bit in register V{:X}\n\
+                 Set register VF to the most significant bit prior to the shift",
+                y, x
+            ),
+            Instruction::SneReg { x, y } => format!("Skip the following instruction if the value of register V{:X} is not equal to the value of register V{:X}", x, y),
+            Instruction::LdI(address) => {
+                format!("Store memory address {:#05X} in register I", address)
+            }
+            Instruction::LdILong(address) => {
+                format!("Store memory address {:#06X} in register I", address)
+            }
+            Instruction::JpV0(address) => format!("Jump to address {:#05X} + V0", address),
+            Instruction::Rnd { x, nn } => format!(
+                "Set V{:X} to a random number with a mask of {:#04X}",
+                x, nn
+            ),
+            Instruction::Drw { x, y, n: 0 } => format!(
+                "Draw a 16x16 sprite at position V{:X}, V{:X} with 32 bytes of sprite data starting at the address stored in I\n\
+                 Set VF to 01 if any set pixels are changed to unset, and 00 otherwise",
+                x, y
+            ),
+            Instruction::Drw { x, y, n } => format!(
+                "Draw a sprite at position V{:X}, V{:X} with {} bytes of sprite data starting at the address stored in I\n\
+                 Set VF to 01 if any set pixels are changed to unset, and 00 otherwise",
+                x, y, n
+            ),
+            Instruction::Skp(x) => format!("Skip the following instruction if the key corresponding to the hex value currently stored in register V{:X} is pressed", x),
+            Instruction::Sknp(x) => format!("Skip the following instruction if the key corresponding to the hex value currently stored in register V{:X} is not pressed", x),
+            Instruction::Plane(n) => format!("Select bitplanes {:#03b} for drawing", n),
+            Instruction::Audio => {
+                "Load the 16 byte audio pattern starting at the address stored in I".to_string()
+            }
+            Instruction::LdVxDt(x) => format!(
+                "Store the current value of the delay timer in register V{:X}",
+                x
+            ),
+            Instruction::LdVxK(x) => format!(
+                "Wait for a key press and store the result in register V{:X}",
+                x
+            ),
+            Instruction::LdDtVx(x) => {
+                format!("Set the delay timer to the value of register V{:X}", x)
+            }
+            Instruction::LdStVx(x) => {
+                format!("Set the sound timer to the value of register V{:X}", x)
+            }
+            Instruction::AddIVx(x) => {
+                format!("Add the value stored in register V{:X} to register I", x)
+            }
+            Instruction::Pitch(x) => format!(
+                "Set the audio pitch to the value of register V{:X}",
+                x
+            ),
+            Instruction::LdFVx(x) => format!("Set I to the memory address of the sprite data corresponding to the hexadecimal digit stored in register V{:X}", x),
+            Instruction::LdHfVx(x) => format!("Set I to the memory address of the large sprite data corresponding to the decimal digit stored in register V{:X}", x),
+            Instruction::LdBVx(x) => format!("Store the binary-coded decimal equivalent of the value stored in register V{:X} at addresses I, I+1, and I+2", x),
+            Instruction::LdIVx(x) => format!(
+                "Store the values of registers V0 to V{0:X} inclusive in memory starting at address I\n\
+                 I is set to I + {0:X} + 1 after operation",
+                x
+            ),
+            Instruction::LdVxI(x) => format!(
+                "Fill registers V0 to V{0:X} inclusive with the values stored in memory starting at address I\n\
+                 I is set to I + {0:X} + 1 after operation",
+                x
+            ),
+            Instruction::LdRVx(x) => format!(
+                "Store the values of registers V0 to V{:X} inclusive in the RPL user flags",
+                x
+            ),
+            Instruction::LdVxR(x) => format!(
+                "Fill registers V0 to V{:X} inclusive with the values stored in the RPL user flags",
+                x
+            ),
+        }
+    }
+}
+
+/// Formats the instruction as an assembly mnemonic, e.g. `DRW V0, V1, 5`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(address) => write!(f, "SYS {:#05X}", address),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(address) => write!(f, "JP {:#05X}", address),
+            Instruction::Call(address) => write!(f, "CALL {:#05X}", address),
+            Instruction::SeImm { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SneImm { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SeReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LdImm { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddImm { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::LdReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(address) => write!(f, "LD I, {:#05X}", address),
+            Instruction::LdILong(address) => write!(f, "LD I, LONG {:#06X}", address),
+            Instruction::JpV0(address) => write!(f, "JP V0, {:#05X}", address),
+            Instruction::Rnd { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_round_trips() {
+        let mut decoded = 0;
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+                assert_eq!(instruction.size(), 2);
+                assert_eq!(instruction.to_bytes(), opcode.to_be_bytes());
+                assert_eq!(Instruction::decode_pair(opcode, 0xFFFF), Some(instruction));
+                decoded += 1;
+            }
+        }
+
+        // Eleven prefixes take any operands, 5XYN has 3 forms, 8XYN 9, 9XYN 1, EXNN 2, FXNN 14
+        // and there's F002.
+        assert_eq!(decoded, 11 * 4096 + (3 + 9 + 1) * 256 + (2 + 14) * 16 + 1);
+        assert_eq!(Instruction::decode(LONG_PREFIX), None);
+    }
+
+    #[test]
+    fn long_loads_take_two_words() {
+        for &address in &[0x0000, 0x1234, 0xFFFF] {
+            let instruction = Instruction::decode_pair(LONG_PREFIX, address).unwrap();
+            assert_eq!(instruction, Instruction::LdILong(address));
+            assert_eq!(instruction.size(), 4);
+            assert_eq!(instruction.encode(), LONG_PREFIX);
+
+            let bytes = instruction.to_bytes();
+            assert_eq!(bytes.len(), 4);
+            let words = (
+                u16::from_be_bytes([bytes[0], bytes[1]]),
+                u16::from_be_bytes([bytes[2], bytes[3]]),
+            );
+            assert_eq!(
+                Instruction::decode_pair(words.0, words.1),
+                Some(instruction)
+            );
+        }
+    }
+}
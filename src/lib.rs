@@ -0,0 +1,25 @@
+pub mod asm;
+pub mod audio;
+pub mod chip8;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod expr;
+pub mod gdbstub;
+pub mod headless;
+pub mod instruction;
+pub mod movie;
+pub mod quirks;
+pub mod random;
+pub mod recording;
+pub mod rewind;
+pub mod savestate;
+pub mod screenshot;
+pub mod trace;
+pub mod tracediff;
+
+pub use crate::chip8::{Chip8State, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use crate::error::Chip8Error;
+pub use crate::instruction::Instruction;
+pub use crate::quirks::Quirks;
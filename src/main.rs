@@ -1,411 +1,571 @@
-use ggez;
+use ggez::audio::{SoundData, SoundSource, Source};
 use ggez::event;
 use ggez::event::KeyCode;
 use ggez::event::KeyMods;
 use ggez::graphics;
 use ggez::graphics::Rect;
+use ggez::timer;
 
-use rand::Rng;
+use chip_8::asm;
+use chip_8::audio::{AudioBackend, NullAudio, Tone, WavAudio, Waveform};
+use chip_8::chip8::{MIN_INSTRUCTIONS_PER_SECOND, PALETTE, TIMER_HZ};
+use chip_8::dap;
+use chip_8::debugger::{self, Debugger};
+use chip_8::disasm::{self, DisasmMode};
+use chip_8::gdbstub::{self, GdbServer};
+use chip_8::headless::{self, Limit, Stop};
+use chip_8::movie::{Movie, Player};
+use chip_8::random::{self, RandomSource};
+use chip_8::recording::Recorder;
+use chip_8::rewind::{Rewind, RewindConfig};
+use chip_8::savestate;
+use chip_8::screenshot::{self, Palette};
+use chip_8::trace::{OpcodeClass, TraceFilter, TraceFormat, Tracer};
+use chip_8::tracediff::{self, Outcome};
+use chip_8::{Chip8Error, Chip8State, Quirks, SCREEN_HEIGHT, SCREEN_WIDTH};
 
 use std::env;
-use std::fs::File;
+use std::fs;
+use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::SystemTime;
 
-const SCREEN_WIDTH: usize = 64;
-const SCREEN_HEIGHT: usize = 32;
 const PIXEL_SIZE: i32 = 20;
 const WINDOW_WIDTH: f32 = SCREEN_WIDTH as f32 * PIXEL_SIZE as f32;
 const WINDOW_HEIGHT: f32 = SCREEN_HEIGHT as f32 * PIXEL_SIZE as f32;
 
-struct Chip8State {
-    memory: [u8; 4096],
-    pc: u16,
-    registers: [u8; 16],
-    i_register: u16,
-    stack: [u16; 12],
-    sp: u8,
-    delay_timer: u8,
-    sound_timer: u8,
-    keys: [bool; 16],
-    video_buf: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
-}
+const PANEL_WIDTH: f32 = 440.0;
+const MEMORY_PANEL_HEIGHT: f32 = 320.0;
+const PANEL_MARGIN: f32 = 12.0;
+const FONT_SIZE: f32 = 18.0;
+const LINE_HEIGHT: f32 = 20.0;
+const MEMORY_ROWS: usize = 14;
 
-impl Chip8State {
-    fn new(rom: &str) -> ggez::GameResult<Chip8State> {
-        let mut s = Chip8State {
-            memory: [0; 4096],
-            pc: 512,
-            registers: [0; 16],
-            i_register: 0,
-            stack: [0; 12],
-            sp: 0,
-            delay_timer: 0,
-            sound_timer: 0,
-            keys: [false; 16],
-            video_buf: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
-        };
+/// Image pixels per high resolution CHIP-8 pixel in recordings, unless `--record-scale` is given.
+const DEFAULT_RECORD_SCALE: u32 = 4;
 
-        s.load_sprites();
-
-        s.load_rom(rom);
-
-        Ok(s)
-    }
-
-    fn load_sprites(&mut self) {
-        let sprites = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
+/// Which debugger panels are shown around the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overlay {
+    Off,
+    /// Registers, stack and disassembly to the right of the display.
+    Registers,
+    /// As well as a hex view of memory below it.
+    Full,
+}
 
-        for i in 0..sprites.len() {
-            self.memory[i] = sprites[i];
+impl Overlay {
+    fn next(self) -> Overlay {
+        match self {
+            Overlay::Off => Overlay::Registers,
+            Overlay::Registers => Overlay::Full,
+            Overlay::Full => Overlay::Off,
         }
     }
 
-    fn load_rom(&mut self, path: &str) {
-        let mut file = File::open(path).unwrap();
-
-        file.read(&mut self.memory[512..]).unwrap();
+    fn window_size(self) -> (f32, f32) {
+        match self {
+            Overlay::Off => (WINDOW_WIDTH, WINDOW_HEIGHT),
+            Overlay::Registers => (WINDOW_WIDTH + PANEL_WIDTH, WINDOW_HEIGHT),
+            Overlay::Full => (
+                WINDOW_WIDTH + PANEL_WIDTH,
+                WINDOW_HEIGHT + MEMORY_PANEL_HEIGHT,
+            ),
+        }
     }
+}
+
+/// Plays the tone through ggez, looping it for as long as the sound timer runs.
+struct GgezAudio {
+    source: Source,
+    playing: bool,
+}
+
+impl GgezAudio {
+    fn new(ctx: &mut ggez::Context, tone: &Tone) -> ggez::GameResult<GgezAudio> {
+        let data = SoundData::from_bytes(&tone.looped_wav());
+        let mut source = Source::from_data(ctx, data)?;
+        source.set_repeat(true);
 
-    fn get_opcode(&self) -> u16 {
-        (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize] as u16)
+        Ok(GgezAudio {
+            source,
+            playing: false,
+        })
     }
+}
+
+impl AudioBackend for GgezAudio {
+    fn frame(&mut self, tone_on: bool) {
+        if tone_on == self.playing {
+            return;
+        }
 
-    fn process_opcode(&mut self, opcode: u16) {
-        let nibble_one = (opcode & 0xF000) >> 12;
-        let nibble_two = (opcode & 0x0F00) >> 8;
-        let nibble_three = (opcode & 0x00F0) >> 4;
-        let nibble_four = opcode & 0x000F;
+        if tone_on {
+            if let Err(err) = self.source.play() {
+                eprintln!("Unable to play sound: {}", err);
+            }
+        } else {
+            self.source.stop();
+        }
+        self.playing = tone_on;
+    }
+}
 
-        let reg_x = nibble_two as usize;
-        let reg_y = nibble_three as usize;
-        let opcode_nn = (opcode & 0x00FF) as u8;
-        let address = opcode & 0x0FFF;
+struct Frontend {
+    chip8: Chip8State,
+    rom: PathBuf,
+    audio: Box<dyn AudioBackend>,
+    rewind: Rewind,
+    rewinding: bool,
+    debugger: Debugger,
+    console: Option<Receiver<String>>,
+    gdb: Option<GdbServer>,
+    overlay: Overlay,
+    palette: Palette,
+    /// Image pixels per CHIP-8 pixel in screenshots, or `None` to match the window.
+    screenshot_scale: Option<u32>,
+    recorder: Option<Recorder>,
+    /// Key presses being recorded, and the file to save them to.
+    movie: Option<(Movie, PathBuf)>,
+    player: Option<Player>,
+    /// Image pixels per high resolution CHIP-8 pixel in recordings.
+    record_scale: u32,
+    error: Option<Chip8Error>,
+}
 
-        print!("pc: {:#05X}, {:04X} : ", self.pc, opcode);
+impl Frontend {
+    fn new(
+        chip8: Chip8State,
+        rom: PathBuf,
+        audio: Box<dyn AudioBackend>,
+        rewind: RewindConfig,
+    ) -> Frontend {
+        Frontend {
+            chip8,
+            rom,
+            audio,
+            rewind: Rewind::new(rewind),
+            rewinding: false,
+            debugger: Debugger::new(),
+            console: None,
+            gdb: None,
+            overlay: Overlay::Off,
+            palette: PALETTE,
+            screenshot_scale: None,
+            recorder: None,
+            movie: None,
+            player: None,
+            record_scale: DEFAULT_RECORD_SCALE,
+            error: None,
+        }
+    }
 
-        match (nibble_one, nibble_two, nibble_three, nibble_four) {
-            (0, 0, 0xE, 0) => {
-                println!("Clear the screen");
-                for row in 0..SCREEN_HEIGHT {
-                    for col in 0..SCREEN_WIDTH {
-                        self.video_buf[row][col] = false;
+    /// Start paused, taking debugger commands from standard input.
+    fn attach_console(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
                     }
+                    Err(_) => break,
                 }
             }
-            (0, 0, 0xE, 0xE) => {
-                println!("Return from a subroutine");
-                self.sp -= 1;
-                self.pc = self.stack[self.sp as usize];
-                return;
-            }
-            (0, _, _, _) => {
-                println!(
-                    "Execute machine language subroutine at address {:#05X}",
-                    address
-                );
-                panic!("{:04X} not implemented", opcode);
-            }
-            (1, _, _, _) => {
-                println!("Jump to address {:#05X}", address);
-                self.pc = address;
-                return;
-            }
-            (2, _, _, _) => {
-                println!("Execute subroutine starting at address {:#05X}", address);
-                self.stack[self.sp as usize] = self.pc + 2;
-                self.sp += 1;
-                self.pc = address;
-                return;
-            }
-            (3, _, _, _) => {
-                println!(
-                    "Skip the following instruction if the value of register V{:X} equals {:#04X}",
-                    reg_x, opcode_nn
-                );
-                if self.registers[reg_x] == opcode_nn {
-                    self.pc += 2;
-                }
+        });
+
+        self.console = Some(receiver);
+        self.debugger.pause();
+        println!("{}", debugger::describe_pc(&self.chip8));
+        prompt();
+    }
+
+    fn run_console_commands(&mut self) {
+        let lines: Vec<String> = match &self.console {
+            Some(console) => console.try_iter().collect(),
+            None => return,
+        };
+
+        for line in lines {
+            match self.debugger.command(&mut self.chip8, &line) {
+                Ok(output) if output.is_empty() => (),
+                Ok(output) => println!("{}", output.trim_end()),
+                Err(err) => println!("error: {}", err),
             }
-            (4, _, _, _) => {
-                println!("Skip the following instruction if the value of register V{:X} is not equal to {:#04X}", reg_x, opcode_nn);
-                if self.registers[reg_x] != opcode_nn {
-                    self.pc += 2;
-                }
+            prompt();
+        }
+    }
+
+    fn poll_gdb(&mut self) {
+        if let Some(gdb) = self.gdb.as_mut() {
+            if let Err(err) = gdb.poll(&mut self.chip8, &mut self.debugger) {
+                eprintln!("GDB connection lost: {}", err);
             }
-            (5, _, _, _) => {
-                println!("Skip the following instruction if the value of register V{:X} is equal to the value of register V{:X}", reg_x, reg_y);
-                if self.registers[reg_x] == self.registers[reg_y] {
-                    self.pc += 2;
-                }
+        }
+    }
+
+    fn report_to_gdb(&mut self, signal: u8) {
+        if let Some(gdb) = self.gdb.as_mut() {
+            if let Err(err) = gdb.report_stop(signal) {
+                eprintln!("GDB connection lost: {}", err);
             }
-            (6, _, _, _) => {
-                println!("Store number {:#04X} in register V{:X}", opcode_nn, reg_x);
-                self.registers[reg_x] = opcode_nn;
-            }
-            (7, _, _, _) => {
-                println!("Add the value {:#04X} to register V{:X}", opcode_nn, reg_x);
-                self.registers[reg_x] += opcode_nn;
-            }
-            (8, _, _, 0) => {
-                println!(
-                    "Store the value of register V{:X} in register V{:X}",
-                    reg_y, reg_x
-                );
-                self.registers[reg_x] = self.registers[reg_y];
-            }
-            (8, _, _, 1) => {
-                println!("Set V{0:X} to V{0:X} OR V{1:X}", reg_x, reg_y);
-                self.registers[reg_x] |= self.registers[reg_y];
-            }
-            (8, _, _, 2) => {
-                println!("Set V{0:X} to V{0:X} AND V{1:X}", reg_x, reg_y);
-                self.registers[reg_x] &= self.registers[reg_y];
-            }
-            (8, _, _, 3) => {
-                println!("Set V{0:X} to V{0:X} XOR V{1:X}", reg_x, reg_y);
-                self.registers[reg_x] ^= self.registers[reg_y];
-            }
-            (8, _, _, 4) => {
-                println!(
-                    "Add the value of register V{:X} to register V{:X}",
-                    reg_y, reg_x
-                );
-                println!("Set VF to 01 if borrow occurs");
-                println!("Set VF to 00 if borrow does not occur");
-                if self.registers[reg_x] as u16 + self.registers[reg_y] as u16 > 0xFF {
-                    self.registers[0xF] = 1;
-                } else {
-                    self.registers[0xF] = 0;
-                }
-                self.registers[reg_x] += self.registers[reg_y];
-            }
-            (8, _, _, 5) => {
-                println!(
-                    "Subtract the value of register V{:X} from register V{:X}",
-                    reg_y, reg_x
-                );
-                println!("Set VF to 00 if borrow occurs");
-                println!("Set VF to 01 if borrow does not occur");
-                if self.registers[reg_x] > self.registers[reg_y] {
-                    self.registers[0xF] = 1;
-                } else {
-                    self.registers[0xF] = 0;
-                }
-                self.registers[reg_x] -= self.registers[reg_y];
-            }
-            (8, _, _, 6) => {
-                println!(
-                    "Store the value of register V{:X} shifted right one bit in register V{:X}",
-                    reg_y, reg_x
-                );
-                println!("Set register VF to the least significant bit prior to the shift");
-                self.registers[0xF] = self.registers[reg_x] & 0x1;
-                self.registers[reg_x] = self.registers[reg_y] >> 1;
-            }
-            (8, _, _, 7) => {
-                println!(
-                    "Set register V{0:X} to the value of V{1:X} minus V{0:X}",
-                    reg_x, reg_y
-                );
-                println!("Set VF to 00 if a borrow occurs");
-                println!("Set VF to 01 if a borrow does not occur");
-                if self.registers[reg_y] > self.registers[reg_x] {
-                    self.registers[0xF] = 1;
-                } else {
-                    self.registers[0xF] = 0;
-                }
-                self.registers[reg_x] = self.registers[reg_y] - self.registers[reg_x];
-            }
-            (8, _, _, 0xE) => {
-                println!(
-                    "Store the value of register V{:X} shifted left one bit in register V{:X}",
-                    reg_y, reg_x
-                );
-                println!("Set register VF to the most significant bit prior to the shift");
-                self.registers[0xF] = (self.registers[reg_x] & 0x8) >> 7;
-                self.registers[reg_x] = self.registers[reg_y] << 1;
-            }
-            (9, _, _, 0) => {
-                println!("Skip the following instruction if the value of register V{:X} is not equal to the value of register V{:X}", reg_x, reg_y);
-                if self.registers[reg_x] != self.registers[reg_y] {
-                    self.pc += 2;
-                }
+        }
+    }
+
+    fn stopped(&mut self, message: &str) {
+        println!("{}", message);
+        println!("{}", debugger::describe_pc(&self.chip8));
+        if self.console.is_some() {
+            prompt();
+        }
+        self.report_to_gdb(gdbstub::SIGTRAP);
+    }
+
+    fn save_state(&self, slot: u8) {
+        let path = savestate::slot_path(&self.rom, slot);
+        match savestate::save_file(&self.chip8, &path) {
+            Ok(()) => println!("Saved state {} to {}", slot, path.display()),
+            Err(err) => eprintln!("Unable to save state {}: {}", slot, err),
+        }
+    }
+
+    fn load_state(&mut self, slot: u8) {
+        let path = savestate::slot_path(&self.rom, slot);
+        match savestate::load_file(&mut self.chip8, &path) {
+            Ok(()) => {
+                println!("Loaded state {} from {}", slot, path.display());
+                self.error = None;
+                self.end_movie("loaded a state");
+            }
+            Err(err) => eprintln!("Unable to load state {}: {}", slot, err),
+        }
+    }
+
+    fn screenshot(&self) {
+        let name = screenshot::file_name(&self.rom, SystemTime::now(), "png");
+        let path = self.rom.with_file_name(name);
+        let scale = self
+            .screenshot_scale
+            .unwrap_or_else(|| self.pixel_size() as u32);
+
+        match screenshot::write_png(&self.chip8, &path, scale, &self.palette) {
+            Ok(()) => println!("Saved screenshot to {}", path.display()),
+            Err(err) => eprintln!("Unable to save screenshot: {}", err),
+        }
+    }
+
+    fn start_recording<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        match Recorder::create(path, self.record_scale, &self.palette) {
+            Ok(recorder) => {
+                println!("Recording to {}", path.display());
+                self.recorder = Some(recorder);
+            }
+            Err(err) => eprintln!("Unable to record to {}: {}", path.display(), err),
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            let frames = recorder.frames_recorded();
+            match recorder.finish() {
+                Ok(()) => println!("Recorded {} frames", frames),
+                Err(err) => eprintln!("Unable to finish recording: {}", err),
             }
-            (0xA, _, _, _) => {
-                println!("Store memory address {:#05X} in register I", address);
-                self.i_register = address;
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.recorder.is_some() {
+            self.stop_recording();
+        } else {
+            let name = screenshot::file_name(&self.rom, SystemTime::now(), "gif");
+            self.start_recording(self.rom.with_file_name(name));
+        }
+    }
+
+    fn record_frame(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(err) = recorder.frame(&self.chip8) {
+                eprintln!("Recording stopped: {}", err);
+                self.recorder = None;
             }
-            (0xB, _, _, _) => {
-                println!("Jump to address {:#05X} + V0", address);
-                self.pc = address + self.registers[0] as u16;
-                return;
+        }
+    }
+
+    // Replay or record key presses before the machine runs.
+    fn movie_input(&mut self) {
+        if let Some(player) = self.player.as_mut() {
+            player.apply(&mut self.chip8);
+            if player.finished(&self.chip8) {
+                println!("Movie finished");
+                self.player = None;
             }
-            (0xC, _, _, _) => {
-                println!(
-                    "Set V{:X} to a random number with a mask of {:#04X}",
-                    reg_x, opcode_nn
-                );
-                let random_num = rand::thread_rng().gen_range(0x00, 0xFF);
-                self.registers[reg_x] = random_num & opcode_nn;
-            }
-            (0xD, _, _, _) => {
-                println!("Draw a sprite at position V{:X}, V{:X} with {} bytes of sprite data starting at the address stored in I", reg_x, reg_y, opcode & 0x000F);
-                println!("Set VF to 01 if any set pixels are changed to unset, and 00 otherwise");
-                let sprite_rows = (opcode & 0x000F) as u8;
-                self.registers[0xF] = 0;
-                for sprite_row in 0..sprite_rows {
-                    for sprite_col in 0..8 {
-                        let screen_row: usize = (self.registers[reg_y] + sprite_row) as usize;
-                        let screen_col: usize = (self.registers[reg_x] + sprite_col) as usize;
-                        let sprite_pixel = (self.memory
-                            [(self.i_register + sprite_row as u16) as usize]
-                            & (0x80 >> sprite_col))
-                            >> 7 - sprite_col;
-
-                        if screen_row < SCREEN_HEIGHT && screen_col < SCREEN_WIDTH {
-                            if sprite_pixel == 1 {
-                                if self.video_buf[screen_row][screen_col] == true {
-                                    self.registers[0xF] = 1;
-                                }
-                                self.video_buf[screen_row][screen_col] ^= true;
-                            }
-                        }
-                    }
-                }
+        }
+        if let Some((movie, _)) = self.movie.as_mut() {
+            movie.record(&self.chip8);
+        }
+    }
+
+    /// Stop recording or playing a movie, which would no longer match the session after
+    /// jumping to another point in time.
+    fn end_movie(&mut self, reason: &str) {
+        if self.player.take().is_some() {
+            println!("Movie playback stopped: {}", reason);
+        }
+        if self.movie.is_some() {
+            println!("Movie recording stopped: {}", reason);
+            self.save_movie();
+        }
+    }
+
+    fn save_movie(&mut self) {
+        if let Some((mut movie, path)) = self.movie.take() {
+            movie.record(&self.chip8);
+            match movie.save_file(&path) {
+                Ok(()) => println!("Saved movie to {}", path.display()),
+                Err(err) => eprintln!("Unable to save movie: {}", err),
             }
-            (0xE, _, 9, 0xE) => {
-                println!("Skip the following instruction if the key corresponding to the hex value currently stored in register V{:X} is pressed", reg_x);
-                if self.keys[self.registers[reg_x] as usize] == true {
-                    self.pc += 2;
-                }
+        }
+    }
+
+    // High resolution mode draws twice as many pixels at half the size.
+    fn pixel_size(&self) -> i32 {
+        PIXEL_SIZE * SCREEN_WIDTH as i32 / self.chip8.screen_width() as i32
+    }
+
+    fn palette_color(&self, pixel: u8) -> graphics::Color {
+        let [r, g, b] = self.palette[pixel as usize];
+        graphics::Color::from_rgb(r, g, b)
+    }
+
+    fn halt(&mut self, err: Chip8Error) {
+        eprintln!("Emulation stopped: {}", err);
+        eprint!("{}", debugger::dump_state(&self.chip8));
+
+        self.error = Some(err);
+        self.report_to_gdb(gdbstub::SIGILL);
+    }
+
+    fn register_panel(&self) -> Vec<String> {
+        let chip8 = &self.chip8;
+        let status = match &self.error {
+            Some(err) => format!("Stopped: {}", err),
+            None if self.debugger.is_paused() => "Paused".to_string(),
+            None if chip8.halted => "Exited".to_string(),
+            None => "Running".to_string(),
+        };
+
+        let mut lines = vec![
+            status,
+            format!("PC {:#05X}   I {:#05X}", chip8.pc, chip8.i_register),
+            format!(
+                "DT {:<3}     ST {:<3}   SP {}",
+                chip8.delay_timer, chip8.sound_timer, chip8.sp
+            ),
+        ];
+        for (row, values) in chip8.registers.chunks(4).enumerate() {
+            let cells: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(col, value)| format!("V{:X} {:02X}", row * 4 + col, value))
+                .collect();
+            lines.push(cells.join("   "));
+        }
+
+        lines.push(String::new());
+        lines.push("Stack".to_string());
+        if chip8.sp == 0 {
+            lines.push("  (empty)".to_string());
+        }
+        for (depth, address) in chip8.stack[..chip8.sp as usize].iter().enumerate().rev() {
+            lines.push(format!("  {:>2}: {:#05X}", depth, address));
+        }
+
+        lines.push(String::new());
+        lines.extend(debugger::listing(chip8, self.debugger.breakpoints(), 4, 12));
+
+        lines
+    }
+
+    fn memory_panel(&self) -> Vec<String> {
+        // Keep I on the third row so a little of what precedes it is visible.
+        let start = (self.chip8.i_register & !0xF).saturating_sub(0x20);
+        let dump = debugger::dump_memory(&self.chip8, start, MEMORY_ROWS * 16);
+
+        let mut lines = vec![format!("Memory around I ({:#05X})", self.chip8.i_register)];
+        lines.extend(dump.lines().map(str::to_string));
+        lines
+    }
+
+    fn draw_panels(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+        let (width, height) = self.overlay.window_size();
+        let background = graphics::Color::from_rgb(0x20, 0x20, 0x28);
+
+        let side = Rect::new(WINDOW_WIDTH, 0.0, PANEL_WIDTH, height);
+        draw_rect(ctx, side, background)?;
+        draw_lines(
+            ctx,
+            &self.register_panel(),
+            WINDOW_WIDTH + PANEL_MARGIN,
+            PANEL_MARGIN,
+        )?;
+
+        if self.overlay == Overlay::Full {
+            let bottom = Rect::new(0.0, WINDOW_HEIGHT, width, MEMORY_PANEL_HEIGHT);
+            draw_rect(ctx, bottom, background)?;
+            draw_lines(
+                ctx,
+                &self.memory_panel(),
+                PANEL_MARGIN,
+                WINDOW_HEIGHT + PANEL_MARGIN,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn draw_rect(ctx: &mut ggez::Context, rect: Rect, color: graphics::Color) -> ggez::GameResult {
+    let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), rect, color)?;
+    graphics::draw(ctx, &rectangle, (ggez::mint::Point2 { x: 0.0, y: 0.0 },))
+}
+
+fn draw_lines(ctx: &mut ggez::Context, lines: &[String], x: f32, y: f32) -> ggez::GameResult {
+    for (i, line) in lines.iter().enumerate() {
+        let mut text = graphics::Text::new(line.as_str());
+        text.set_font(
+            graphics::Font::default(),
+            graphics::Scale::uniform(FONT_SIZE),
+        );
+        let position = ggez::mint::Point2 {
+            x,
+            y: y + i as f32 * LINE_HEIGHT,
+        };
+        graphics::draw(ctx, &text, (position, graphics::WHITE))?;
+    }
+
+    Ok(())
+}
+
+fn resize(ctx: &mut ggez::Context, overlay: Overlay) -> ggez::GameResult {
+    let (width, height) = overlay.window_size();
+    graphics::set_drawable_size(ctx, width, height)?;
+    graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, width, height))
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    io::stdout().flush().ok();
+}
+
+fn key_index(keycode: KeyCode) -> Option<usize> {
+    match keycode {
+        KeyCode::Key1 => Some(0x1),
+        KeyCode::Key2 => Some(0x2),
+        KeyCode::Key3 => Some(0x3),
+        KeyCode::Key4 => Some(0xC),
+        KeyCode::Q => Some(0x4),
+        KeyCode::W => Some(0x5),
+        KeyCode::E => Some(0x6),
+        KeyCode::R => Some(0xD),
+        KeyCode::A => Some(0x7),
+        KeyCode::S => Some(0x8),
+        KeyCode::D => Some(0x9),
+        KeyCode::F => Some(0xE),
+        KeyCode::Z => Some(0xA),
+        KeyCode::X => Some(0x0),
+        KeyCode::C => Some(0xB),
+        KeyCode::V => Some(0xF),
+        _ => None,
+    }
+}
+
+fn save_slot(keycode: KeyCode) -> Option<u8> {
+    match keycode {
+        KeyCode::F1 => Some(1),
+        KeyCode::F2 => Some(2),
+        KeyCode::F3 => Some(3),
+        KeyCode::F4 => Some(4),
+        KeyCode::F5 => Some(5),
+        KeyCode::F6 => Some(6),
+        KeyCode::F7 => Some(7),
+        KeyCode::F8 => Some(8),
+        KeyCode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+impl event::EventHandler for Frontend {
+    fn update(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+        self.run_console_commands();
+        self.poll_gdb();
+
+        while timer::check_update_time(ctx, TIMER_HZ) {
+            // Record what was on screen for this frame, even while paused.
+            self.record_frame();
+            if !self.rewinding {
+                self.movie_input();
             }
-            (0xE, _, 0xA, 1) => {
-                println!("Skip the following instruction if the key corresponding to the hex value currently stored in register V{:X} is not pressed", reg_x);
-                if self.keys[self.registers[reg_x] as usize] == false {
-                    self.pc += 2;
+
+            if self.rewinding {
+                match self.rewind.rewind(&mut self.chip8) {
+                    Ok(true) => self.error = None,
+                    Ok(false) => (),
+                    Err(err) => eprintln!("Unable to rewind: {}", err),
                 }
+                self.audio.frame(false);
+                continue;
             }
-            (0xF, _, 0, 7) => {
-                println!(
-                    "Store the current value of the delay timer in register V{:X}",
-                    reg_x
-                );
-                self.registers[reg_x] = self.delay_timer;
-            }
-            (0xF, _, 0, 0xA) => {
-                println!(
-                    "Wait for a key press and store the result in register V{:X}",
-                    reg_x
-                );
-                let mut key_pressed = false;
-                for i in 0..self.keys.len() {
-                    if self.keys[i] == true {
-                        self.registers[reg_x] = i as u8;
-                        key_pressed = true;
-                        break;
-                    }
-                }
-                if !key_pressed {
-                    return;
-                }
+
+            if self.error.is_some() {
+                continue;
             }
-            (0xF, _, 1, 5) => {
-                println!("Set the delay timer to the value of register V{:X}", reg_x);
-                self.delay_timer = self.registers[reg_x];
-            }
-            (0xF, _, 1, 8) => {
-                println!("Set the sound timer to the value of register V{:X}", reg_x);
-                self.sound_timer = self.registers[reg_x];
-            }
-            (0xF, _, 1, 0xE) => {
-                println!(
-                    "Add the value stored in register V{:X} to register I",
-                    reg_x
-                );
-                self.i_register += self.registers[reg_x] as u16;
-            }
-            (0xF, _, 2, 9) => {
-                println!("Set I to the memory address of the sprite data corresponding to the hexadecimal digit stored in register V{:X}", reg_x);
-                self.i_register = (self.registers[reg_x] * 5) as u16;
-            }
-            (0xF, _, 3, 3) => {
-                println!("Store the binary-coded decimal equivalent of the value stored in register V{:X} at addresses I, I+1, and I+2", reg_x);
-                self.memory[self.i_register as usize] = (self.registers[reg_x] / 100) % 10; // Hundreds
-                self.memory[self.i_register as usize + 1] = (self.registers[reg_x] / 10) % 10; // Tens
-                self.memory[self.i_register as usize + 2] = self.registers[reg_x] % 10; // Ones
-            }
-            (0xF, _, 5, 5) => {
-                println!("Store the values of registers V0 to V{:X} inclusive in memory starting at address I", reg_x);
-                println!("I is set to I + {:X} + 1 after operation", reg_x);
-                for i in 0..=reg_x {
-                    self.memory[self.i_register as usize + i] = self.registers[i];
-                }
-                self.i_register += reg_x as u16 + 1;
+
+            if self.debugger.is_paused() {
+                self.audio.frame(false);
+                continue;
             }
-            (0xF, _, 6, 5) => {
-                println!("Fill registers V0 to V{:X} inclusive with the values stored in memory starting at address I", reg_x);
-                println!("I is set to I + {:X} + 1 after operation", reg_x);
-                for i in 0..=reg_x {
-                    self.registers[i] = self.memory[self.i_register as usize + i];
+
+            match self.debugger.run_frame(&mut self.chip8) {
+                Ok(Some(reason)) => self.stopped(&format!("Stopped: {}", reason)),
+                Ok(None) => (),
+                Err(err) => {
+                    self.halt(err);
+                    self.audio.frame(false);
+                    continue;
                 }
-                self.i_register += reg_x as u16 + 1;
-            }
-            _ => {
-                panic!("Unknown opcode: {:04X}", opcode);
             }
-        }
 
-        self.pc += 2;
-    }
-}
-
-impl event::EventHandler for Chip8State {
-    fn update(&mut self, _ctx: &mut ggez::Context) -> ggez::GameResult {
-        let opcode = self.get_opcode();
-        self.process_opcode(opcode);
-
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
-
-        if self.sound_timer > 0 {
-            // TODO: BEEP!
-            println!("BEEP!");
-            self.sound_timer -= 1;
+            self.audio.frame(self.chip8.sound_active());
+            self.rewind.frame(&self.chip8);
         }
 
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
-        graphics::clear(ctx, [0.0, 0.0, 0.0, 1.0].into());
+        graphics::clear(ctx, self.palette_color(0));
 
-        for row in 0..SCREEN_HEIGHT {
-            for col in 0..SCREEN_WIDTH {
-                if self.video_buf[row][col] == true {
-                    let color = [1.0, 1.0, 1.0, 1.0].into();
+        let pixel_size = self.pixel_size();
+
+        for row in 0..self.chip8.screen_height() {
+            for col in 0..self.chip8.screen_width() {
+                let pixel = self.chip8.video_buf[row][col];
+                if pixel != 0 {
+                    let color = self.palette_color(pixel);
                     let rect = Rect::new_i32(
-                        col as i32 * PIXEL_SIZE,
-                        row as i32 * PIXEL_SIZE,
-                        PIXEL_SIZE,
-                        PIXEL_SIZE,
+                        col as i32 * pixel_size,
+                        row as i32 * pixel_size,
+                        pixel_size,
+                        pixel_size,
                     );
                     let rectangle = graphics::Mesh::new_rectangle(
                         ctx,
@@ -418,131 +578,638 @@ impl event::EventHandler for Chip8State {
             }
         }
 
+        if self.overlay != Overlay::Off {
+            self.draw_panels(ctx)?;
+        }
+
         graphics::present(ctx)?;
         Ok(())
     }
 
     fn key_down_event(
         &mut self,
-        _ctx: &mut ggez::Context,
+        ctx: &mut ggez::Context,
         keycode: KeyCode,
-        _keymods: KeyMods,
-        _repeat: bool,
+        keymods: KeyMods,
+        repeat: bool,
     ) {
-        match keycode {
-            KeyCode::Key1 => {
-                self.keys[0x1] = true;
-            }
-            KeyCode::Key2 => {
-                self.keys[0x2] = true;
-            }
-            KeyCode::Key3 => {
-                self.keys[0x3] = true;
-            }
-            KeyCode::Key4 => {
-                self.keys[0xC] = true;
-            }
-            KeyCode::Q => {
-                self.keys[0x4] = true;
-            }
-            KeyCode::W => {
-                self.keys[0x5] = true;
-            }
-            KeyCode::E => {
-                self.keys[0x6] = true;
-            }
-            KeyCode::R => {
-                self.keys[0xD] = true;
-            }
-            KeyCode::A => {
-                self.keys[0x7] = true;
-            }
-            KeyCode::S => {
-                self.keys[0x8] = true;
-            }
-            KeyCode::D => {
-                self.keys[0x9] = true;
-            }
-            KeyCode::F => {
-                self.keys[0xE] = true;
+        // The keypad is driven by the movie while one plays.
+        if let Some(key) = key_index(keycode).filter(|_| self.player.is_none()) {
+            self.chip8.keys[key] = true;
+        }
+
+        if keycode == KeyCode::Back {
+            self.end_movie("rewound");
+            self.rewinding = true;
+        }
+
+        if !repeat && keycode == KeyCode::F10 {
+            if self.debugger.is_paused() {
+                self.debugger.resume();
+            } else {
+                self.debugger.pause();
+                self.stopped("Paused");
             }
-            KeyCode::Z => {
-                self.keys[0xA] = true;
+        }
+
+        if !repeat && keycode == KeyCode::P {
+            self.screenshot();
+        }
+
+        if !repeat && keycode == KeyCode::G {
+            self.toggle_recording();
+        }
+
+        if !repeat && keycode == KeyCode::F12 {
+            self.overlay = self.overlay.next();
+            if let Err(err) = resize(ctx, self.overlay) {
+                eprintln!("Unable to resize window: {}", err);
             }
-            KeyCode::X => {
-                self.keys[0x0] = true;
+        }
+
+        if keycode == KeyCode::F11 && self.error.is_none() {
+            self.movie_input();
+            match self.debugger.step(&mut self.chip8) {
+                Ok(()) => self.stopped("Stepped"),
+                Err(err) => self.halt(err),
             }
-            KeyCode::C => {
-                self.keys[0xB] = true;
+        }
+
+        if let Some(slot) = save_slot(keycode) {
+            if repeat {
+                return;
             }
-            KeyCode::V => {
-                self.keys[0xF] = true;
+            if keymods.contains(KeyMods::SHIFT) {
+                self.save_state(slot);
+            } else {
+                self.load_state(slot);
             }
-            _ => (),
         }
     }
 
     fn key_up_event(&mut self, _ctx: &mut ggez::Context, keycode: KeyCode, _keymods: KeyMods) {
-        match keycode {
-            KeyCode::Key1 => {
-                self.keys[0x1] = false;
-            }
-            KeyCode::Key2 => {
-                self.keys[0x2] = false;
-            }
-            KeyCode::Key3 => {
-                self.keys[0x3] = false;
-            }
-            KeyCode::Key4 => {
-                self.keys[0xC] = false;
-            }
-            KeyCode::Q => {
-                self.keys[0x4] = false;
-            }
-            KeyCode::W => {
-                self.keys[0x5] = false;
-            }
-            KeyCode::E => {
-                self.keys[0x6] = false;
-            }
-            KeyCode::R => {
-                self.keys[0xD] = false;
-            }
-            KeyCode::A => {
-                self.keys[0x7] = false;
-            }
-            KeyCode::S => {
-                self.keys[0x8] = false;
-            }
-            KeyCode::D => {
-                self.keys[0x9] = false;
-            }
-            KeyCode::F => {
-                self.keys[0xE] = false;
-            }
-            KeyCode::Z => {
-                self.keys[0xA] = false;
-            }
-            KeyCode::X => {
-                self.keys[0x0] = false;
+        if let Some(key) = key_index(keycode).filter(|_| self.player.is_none()) {
+            self.chip8.keys[key] = false;
+        }
+
+        if keycode == KeyCode::Back {
+            self.rewinding = false;
+        }
+    }
+}
+
+const USAGE: &str = "Usage:
+    chip_8 [--ips INSTRUCTIONS_PER_SECOND] [--quirks vip|chip48|schip|octo]
+           [--tone HZ] [--waveform square|sine|triangle|sawtooth] [--volume 0-1]
+           [--mute | --audio-wav FILE]
+           [--rewind-seconds SECONDS] [--rewind-memory MB] [--debug] [--gdb PORT]
+           [--trace FILE|-] [--trace-format text|csv|json] [--trace-range START-END]
+           [--trace-only CLASS,...] [--trace-registers] [--seed N | --random SOURCE]
+           [--palette RRGGBB,RRGGBB[,...]] [--screenshot-scale N]
+           [--record FILE.gif|DIR] [--record-scale N]
+           [--movie FILE] [--movie-record FILE] ROM
+    chip_8 disasm [--linear | --recursive] ROM
+    chip_8 asm SOURCE [-o OUTPUT]
+    chip_8 headless [--frames N | --cycles N] [--keys SCRIPT] [--stop-on-loop]
+                    [--quirks PRESET] [--ips N] [--seed N | --random SOURCE]
+                    [--screen-text FILE|-] [--screen-png FILE|DIR] [--png-scale N]
+                    [--palette RRGGBB,RRGGBB[,...]] [--expect-screen FILE]
+                    [--record FILE.gif|DIR] [--record-scale N] [--movie FILE] ROM
+    chip_8 tracediff [--quirks PRESET] [--seed N | --random SOURCE] [--context N]
+                     REFERENCE ROM
+    chip_8 dap
+
+SOURCE is seed:N, vip or script:HEX,HEX,... for the numbers CXNN uses.";
+
+enum AudioOutput {
+    Speakers,
+    Mute,
+    Wav(String),
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut chip8 = Chip8State::new();
+    let mut tone = Tone::default();
+    let mut output = AudioOutput::Speakers;
+    let mut rewind = RewindConfig::default();
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::default();
+    let mut trace_registers = false;
+    let mut palette = PALETTE;
+    let mut screenshot_scale = None;
+    let mut record_path = None;
+    let mut record_scale = DEFAULT_RECORD_SCALE;
+    let mut movie = None;
+    let mut movie_path = None;
+    let mut rom = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ips" => {
+                chip8.instructions_per_second = parse_ips(args.next())?;
+            }
+            "--quirks" => {
+                chip8.quirks = parse_quirks(args.next())?;
+            }
+            "--tone" => {
+                tone.frequency = parse_option(args.next())?;
+                if tone.frequency <= 0.0 {
+                    return Err("tone frequency must be positive".to_string());
+                }
             }
-            KeyCode::C => {
-                self.keys[0xB] = false;
+            "--waveform" => {
+                tone.waveform = parse_waveform(args.next())?;
             }
-            KeyCode::V => {
-                self.keys[0xF] = false;
+            "--volume" => {
+                tone.volume = parse_option(args.next())?;
+                if !(0.0..=1.0).contains(&tone.volume) {
+                    return Err("volume must be between 0 and 1".to_string());
+                }
             }
-            _ => (),
+            "--mute" => output = AudioOutput::Mute,
+            "--audio-wav" => {
+                output = AudioOutput::Wav(args.next().ok_or_else(|| USAGE.to_string())?.clone());
+            }
+            "--rewind-seconds" => {
+                let seconds: usize = parse_option(args.next())?;
+                rewind.depth = seconds * TIMER_HZ as usize / rewind.interval as usize;
+            }
+            "--rewind-memory" => {
+                let megabytes: usize = parse_option(args.next())?;
+                rewind.max_bytes = megabytes * 1024 * 1024;
+            }
+            "--debug" => debug = true,
+            "--gdb" => gdb_port = Some(parse_option::<u16>(args.next())?),
+            "--trace" => trace_path = Some(args.next().ok_or_else(|| USAGE.to_string())?),
+            "--trace-format" => trace_format = parse_trace_format(args.next())?,
+            "--trace-range" => trace_filter.addresses = Some(parse_range(args.next())?),
+            "--trace-only" => trace_filter.classes = parse_opcode_classes(args.next())?,
+            "--trace-registers" => trace_registers = true,
+            "--seed" => chip8.seed_random(parse_option(args.next())?),
+            "--random" => chip8.random = parse_random(args.next())?,
+            "--palette" => palette = parse_palette(args.next())?,
+            "--screenshot-scale" => screenshot_scale = Some(parse_option(args.next())?),
+            "--record" => record_path = Some(args.next().ok_or_else(|| USAGE.to_string())?),
+            "--record-scale" => record_scale = parse_option(args.next())?,
+            "--movie" => movie = Some(load_movie(args.next())?),
+            "--movie-record" => {
+                movie_path = Some(args.next().ok_or_else(|| USAGE.to_string())?);
+            }
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(USAGE.to_string()),
         }
     }
-}
 
-fn main() -> ggez::GameResult {
-    let args: Vec<String> = env::args().collect();
+    let rom = rom.ok_or_else(|| USAGE.to_string())?;
+    chip8.load_rom(rom).map_err(|err| err.to_string())?;
+    if let Some(movie) = &movie {
+        movie
+            .prepare(&mut chip8)
+            .map_err(|err| format!("unable to play movie: {}", err))?;
+    }
+    // Recorded after any movie is set up, so a replay can itself be recorded.
+    let movie_recording = movie_path.map(|path| (Movie::new(&chip8), PathBuf::from(path)));
+
+    if let Some(path) = trace_path {
+        let mut tracer = if path == "-" {
+            Tracer::stdout(trace_format)
+        } else {
+            Tracer::create(path, trace_format)
+                .map_err(|err| format!("unable to create {}: {}", path, err))?
+        };
+        tracer.filter = trace_filter;
+        tracer.registers = trace_registers;
+        chip8.tracer = Some(tracer);
+    }
+
+    // The panels are most useful while debugging, so start with them shown.
+    let overlay = if debug { Overlay::Full } else { Overlay::Off };
+    let (width, height) = overlay.window_size();
+
     let (ctx, event_loop) = &mut ggez::ContextBuilder::new("CHIP-8", "Ryan Hope")
         .window_setup(ggez::conf::WindowSetup::default().title("CHIP-8"))
-        .window_mode(ggez::conf::WindowMode::default().dimensions(WINDOW_WIDTH, WINDOW_HEIGHT))
-        .build()?;
-    let state = &mut Chip8State::new(&args[1])?;
-    event::run(ctx, event_loop, state)
+        .window_mode(ggez::conf::WindowMode::default().dimensions(width, height))
+        .build()
+        .map_err(|err| err.to_string())?;
+
+    let audio: Box<dyn AudioBackend> = match output {
+        AudioOutput::Speakers => match GgezAudio::new(ctx, &tone) {
+            Ok(audio) => Box::new(audio),
+            Err(err) => {
+                eprintln!("Audio unavailable, continuing without sound: {}", err);
+                Box::new(NullAudio)
+            }
+        },
+        AudioOutput::Mute => Box::new(NullAudio),
+        AudioOutput::Wav(path) => Box::new(WavAudio::new(path, tone)),
+    };
+
+    let state = &mut Frontend::new(chip8, PathBuf::from(rom), audio, rewind);
+    state.overlay = overlay;
+    state.palette = palette;
+    state.screenshot_scale = screenshot_scale;
+    state.record_scale = record_scale;
+    if let Some(path) = record_path {
+        state.start_recording(path);
+    }
+    state.player = movie.as_ref().map(Player::new);
+    state.movie = movie_recording;
+    if debug {
+        state.attach_console();
+    }
+    if let Some(port) = gdb_port {
+        let gdb = GdbServer::bind(("127.0.0.1", port))
+            .map_err(|err| format!("unable to listen on port {}: {}", port, err))?;
+        println!("Waiting for GDB on 127.0.0.1:{}", port);
+        state.gdb = Some(gdb);
+    }
+    let result = event::run(ctx, event_loop, state).map_err(|err| err.to_string());
+    state.stop_recording();
+    state.save_movie();
+    state
+        .audio
+        .finish()
+        .map_err(|err| format!("unable to write audio: {}", err))?;
+    if let Some(tracer) = state.chip8.tracer.as_mut() {
+        tracer
+            .flush()
+            .map_err(|err| format!("unable to write trace: {}", err))?;
+    }
+    result
+}
+
+fn parse_option<T: FromStr>(value: Option<&String>) -> Result<T, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| USAGE.to_string())
+}
+
+fn parse_ips(value: Option<&String>) -> Result<u32, String> {
+    let ips = parse_option(value)?;
+    if ips < MIN_INSTRUCTIONS_PER_SECOND {
+        return Err(format!(
+            "--ips must be at least {}",
+            MIN_INSTRUCTIONS_PER_SECOND
+        ));
+    }
+
+    Ok(ips)
+}
+
+fn parse_quirks(value: Option<&String>) -> Result<Quirks, String> {
+    let name = value.ok_or_else(|| USAGE.to_string())?;
+
+    Quirks::from_name(name).ok_or_else(|| {
+        format!(
+            "unknown quirks preset '{}', expected one of: {}",
+            name,
+            Quirks::PRESET_NAMES.join(", ")
+        )
+    })
+}
+
+fn parse_waveform(value: Option<&String>) -> Result<Waveform, String> {
+    let name = value.ok_or_else(|| USAGE.to_string())?;
+
+    Waveform::from_name(name).ok_or_else(|| {
+        format!(
+            "unknown waveform '{}', expected one of: {}",
+            name,
+            Waveform::NAMES.join(", ")
+        )
+    })
+}
+
+fn load_movie(path: Option<&String>) -> Result<Movie, String> {
+    let path = path.ok_or_else(|| USAGE.to_string())?;
+
+    Movie::load_file(path).map_err(|err| format!("unable to read {}: {}", path, err))
+}
+
+fn parse_random(value: Option<&String>) -> Result<Box<dyn RandomSource>, String> {
+    let spec = value.ok_or_else(|| USAGE.to_string())?;
+
+    random::parse(spec).ok_or_else(|| {
+        format!(
+            "invalid random source '{}', expected seed:N, vip or script:HEX,HEX,...",
+            spec
+        )
+    })
+}
+
+fn parse_palette(value: Option<&String>) -> Result<Palette, String> {
+    let text = value.ok_or_else(|| USAGE.to_string())?;
+
+    screenshot::parse_palette(text).ok_or_else(|| {
+        format!(
+            "invalid palette '{}', expected two to four RRGGBB colours separated by commas",
+            text
+        )
+    })
+}
+
+fn parse_trace_format(value: Option<&String>) -> Result<TraceFormat, String> {
+    let name = value.ok_or_else(|| USAGE.to_string())?;
+
+    TraceFormat::from_name(name).ok_or_else(|| {
+        format!(
+            "unknown trace format '{}', expected one of: {}",
+            name,
+            TraceFormat::NAMES.join(", ")
+        )
+    })
+}
+
+fn parse_opcode_classes(value: Option<&String>) -> Result<Vec<OpcodeClass>, String> {
+    let names = value.ok_or_else(|| USAGE.to_string())?;
+
+    names
+        .split(',')
+        .map(|name| {
+            OpcodeClass::from_name(name.trim()).ok_or_else(|| {
+                format!(
+                    "unknown instruction class '{}', expected any of: {}",
+                    name,
+                    OpcodeClass::NAMES.join(", ")
+                )
+            })
+        })
+        .collect()
+}
+
+/// Parse an inclusive range of hex addresses such as `200-2FF`, or a single address.
+fn parse_range(value: Option<&String>) -> Result<(u16, u16), String> {
+    let range = value.ok_or_else(|| USAGE.to_string())?;
+    let address = |text: &str| {
+        let text = text.trim();
+        let digits = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix("0X"))
+            .unwrap_or(text);
+        u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address range '{}'", range))
+    };
+
+    match range.split_once('-') {
+        Some((start, end)) => Ok((address(start)?, address(end)?)),
+        None => {
+            let address = address(range)?;
+            Ok((address, address))
+        }
+    }
+}
+
+/// Exit statuses for `headless`, besides 0 for a clean stop and 1 for bad arguments or files.
+const EXIT_EMULATION_ERROR: i32 = 2;
+const EXIT_SCREEN_MISMATCH: i32 = 3;
+
+fn run_headless(args: &[String]) -> Result<(), String> {
+    let mut chip8 = Chip8State::new();
+    let mut config = headless::Config {
+        limit: Limit::Frames(10 * TIMER_HZ as u64),
+        keys: Vec::new(),
+        stop_on_halt_loop: false,
+        movie: None,
+    };
+    let mut limit = None;
+    let mut screen_text = None;
+    let mut screen_png = None;
+    let mut png_scale = 1;
+    let mut palette = PALETTE;
+    let mut expected = None;
+    let mut record_path = None;
+    let mut record_scale = DEFAULT_RECORD_SCALE;
+    let mut rom = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => limit = Some(Limit::Frames(parse_option(args.next())?)),
+            "--cycles" => limit = Some(Limit::Cycles(parse_option(args.next())?)),
+            "--keys" => {
+                let path = args.next().ok_or_else(|| USAGE.to_string())?;
+                let script = fs::read_to_string(path)
+                    .map_err(|err| format!("unable to read {}: {}", path, err))?;
+                config.keys = headless::parse_key_script(&script)
+                    .map_err(|err| format!("{}: {}", path, err))?;
+            }
+            "--stop-on-loop" => config.stop_on_halt_loop = true,
+            "--movie" => config.movie = Some(load_movie(args.next())?),
+            "--quirks" => chip8.quirks = parse_quirks(args.next())?,
+            "--ips" => chip8.instructions_per_second = parse_ips(args.next())?,
+            "--seed" => chip8.seed_random(parse_option(args.next())?),
+            "--random" => chip8.random = parse_random(args.next())?,
+            "--screen-text" => screen_text = Some(args.next().ok_or_else(|| USAGE.to_string())?),
+            "--screen-png" => screen_png = Some(args.next().ok_or_else(|| USAGE.to_string())?),
+            "--png-scale" => png_scale = parse_option(args.next())?,
+            "--palette" => palette = parse_palette(args.next())?,
+            "--expect-screen" => expected = Some(args.next().ok_or_else(|| USAGE.to_string())?),
+            "--record" => record_path = Some(args.next().ok_or_else(|| USAGE.to_string())?),
+            "--record-scale" => record_scale = parse_option(args.next())?,
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let rom = rom.ok_or_else(|| USAGE.to_string())?;
+    chip8.load_rom(rom).map_err(|err| err.to_string())?;
+
+    // A movie runs for as long as it was recorded unless told otherwise.
+    if let Some(movie) = &config.movie {
+        movie
+            .prepare(&mut chip8)
+            .map_err(|err| format!("unable to play movie: {}", err))?;
+        config.limit = Limit::Cycles(movie.length);
+    }
+    if let Some(limit) = limit {
+        config.limit = limit;
+    }
+
+    let mut recorder = match record_path {
+        Some(path) => Some(
+            Recorder::create(path, record_scale, &palette)
+                .map_err(|err| format!("unable to record to {}: {}", path, err))?,
+        ),
+        None => None,
+    };
+    let mut record_error = None;
+
+    let report = headless::run_with(&mut chip8, &config, |chip8| {
+        if let Some(recorder) = recorder.as_mut() {
+            if let Err(err) = recorder.frame(chip8) {
+                record_error = Some(err);
+            }
+        }
+    });
+    eprintln!(
+        "Stopped after {} frames ({} cycles): {}",
+        report.frames, report.cycles, report.stop
+    );
+
+    let text = headless::screen_text(&chip8);
+    match screen_text {
+        Some(path) if path == "-" => print!("{}", text),
+        Some(path) => {
+            fs::write(path, &text).map_err(|err| format!("unable to write {}: {}", path, err))?
+        }
+        None => (),
+    }
+    if let Some(recorder) = recorder {
+        if let Some(err) = record_error {
+            return Err(format!("unable to record: {}", err));
+        }
+        recorder
+            .finish()
+            .map_err(|err| format!("unable to record: {}", err))?;
+    }
+    if let Some(path) = screen_png {
+        let mut path = PathBuf::from(path);
+        if path.is_dir() {
+            path.push(screenshot::file_name(
+                Path::new(rom),
+                SystemTime::now(),
+                "png",
+            ));
+        }
+        screenshot::write_png(&chip8, &path, png_scale, &palette)
+            .map_err(|err| format!("unable to write {}: {}", path.display(), err))?;
+    }
+
+    if let Stop::Error(_) = report.stop {
+        process::exit(EXIT_EMULATION_ERROR);
+    }
+    if let Some(path) = expected {
+        let expected =
+            fs::read_to_string(path).map_err(|err| format!("unable to read {}: {}", path, err))?;
+        // Ignore line ending differences from checking the file out on Windows.
+        if !expected.lines().eq(text.lines()) {
+            eprintln!("Screen does not match {}", path);
+            process::exit(EXIT_SCREEN_MISMATCH);
+        }
+    }
+
+    Ok(())
+}
+
+fn trace_diff(args: &[String]) -> Result<(), String> {
+    let mut chip8 = Chip8State::new();
+    let mut context = 5;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => chip8.quirks = parse_quirks(args.next())?,
+            "--seed" => chip8.seed_random(parse_option(args.next())?),
+            "--random" => chip8.random = parse_random(args.next())?,
+            "--context" => context = parse_option(args.next())?,
+            _ => paths.push(arg),
+        }
+    }
+
+    let (reference, rom) = match paths.as_slice() {
+        [reference, rom] => (reference, rom),
+        _ => return Err(USAGE.to_string()),
+    };
+    chip8.load_rom(rom).map_err(|err| err.to_string())?;
+    let file = fs::File::open(reference)
+        .map_err(|err| format!("unable to read {}: {}", reference, err))?;
+
+    match tracediff::compare(&mut chip8, io::BufReader::new(file), context)
+        .map_err(|err| err.to_string())?
+    {
+        Outcome::Matched { steps } => {
+            println!("All {} steps match", steps);
+            Ok(())
+        }
+        Outcome::Diverged(divergence) => {
+            print!("{}", divergence);
+            Err(format!("traces diverge at step {}", divergence.step))
+        }
+        Outcome::Failed {
+            step,
+            error,
+            before,
+        } => {
+            for entry in &before {
+                println!("  {}", entry);
+            }
+            Err(format!("emulation stopped at step {}: {}", step, error))
+        }
+    }
+}
+
+fn disassemble(args: &[String]) -> Result<(), String> {
+    let mut mode = DisasmMode::Linear;
+    let mut rom = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--linear" => mode = DisasmMode::Linear,
+            "--recursive" => mode = DisasmMode::Recursive,
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let rom = rom.ok_or_else(|| USAGE.to_string())?;
+    let bytes = fs::read(rom).map_err(|err| format!("unable to read {}: {}", rom, err))?;
+
+    print!("{}", disasm::disassemble(&bytes, mode));
+
+    Ok(())
+}
+
+fn assemble(args: &[String]) -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or_else(|| USAGE.to_string())?.clone()),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let source = source.ok_or_else(|| USAGE.to_string())?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let assembly = asm::assemble_file(source).map_err(|err| err.to_string())?;
+    fs::write(&output, &assembly.rom)
+        .map_err(|err| format!("unable to write {}: {}", output, err))?;
+
+    println!("Wrote {} bytes to {}", assembly.rom.len(), output);
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("disasm") => disassemble(&args[1..]),
+        Some("asm") => assemble(&args[1..]),
+        Some("headless") => run_headless(&args[1..]),
+        Some("tracediff") => trace_diff(&args[1..]),
+        Some("dap") if args.len() == 1 => {
+            dap::serve(io::stdin(), io::stdout()).map_err(|err| err.to_string())
+        }
+        Some(_) => run(&args),
+        None => Err(USAGE.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 }
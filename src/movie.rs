@@ -0,0 +1,510 @@
+use crate::chip8::{Chip8State, MIN_INSTRUCTIONS_PER_SECOND};
+use crate::error::Chip8Error;
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::random;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+const HEADER: &str = "chip8-movie";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    UnsupportedVersion(u32),
+    WrongRom { expected: u64, found: u64 },
+    Io(io::Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "movie version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            MovieError::WrongRom { expected, found } => write!(
+                f,
+                "movie is for a different ROM (hash {:016X}, loaded ROM is {:016X})",
+                found, expected
+            ),
+            MovieError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for MovieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MovieError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> MovieError {
+        MovieError::Io(err)
+    }
+}
+
+/// A key pressed or released before the instruction at `cycle` executes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieEvent {
+    pub cycle: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recording of every change to the keypad from power on, with everything else needed to
+/// replay the session exactly: the ROM, quirks, clock rate and where `CXNN` gets its numbers.
+///
+/// Movies are text, so they can be attached to bug reports and diffed:
+///
+/// ```text
+/// chip8-movie 1
+/// rom 8C4F3D3B2A9E0F17
+/// quirks shift_uses_vy load_store_increments_i logic_resets_vf clip_sprites wait_for_vblank
+/// ips 700
+/// random seed:12345
+/// length 4200
+/// 1400 5 down
+/// 1412 5 up
+/// ```
+///
+/// Each event line is `CYCLE KEY down|up` with the key in hex, and `length` is the number of
+/// cycles the session ran for. `random` is left out if the source can't be recreated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    /// The random source as `random::parse` accepts it.
+    pub random: Option<String>,
+    pub length: u64,
+    pub events: Vec<MovieEvent>,
+    /// The keypad as of the last event, to find changes while recording.
+    keys: [bool; 16],
+}
+
+impl Movie {
+    /// Start recording `chip8`, which should have just had its ROM loaded.
+    pub fn new(chip8: &Chip8State) -> Movie {
+        Movie {
+            rom_hash: chip8.rom_hash,
+            quirks: chip8.quirks,
+            instructions_per_second: chip8.instructions_per_second,
+            random: chip8.random.spec(),
+            length: chip8.cycles,
+            events: Vec::new(),
+            keys: chip8.keys,
+        }
+    }
+
+    /// Record any keys that changed since the last call. Call before running each frame or
+    /// step, so events land on the cycle they first affect.
+    pub fn record(&mut self, chip8: &Chip8State) {
+        for key in 0..self.keys.len() {
+            if chip8.keys[key] != self.keys[key] {
+                self.events.push(MovieEvent {
+                    cycle: chip8.cycles,
+                    key: key as u8,
+                    pressed: chip8.keys[key],
+                });
+            }
+        }
+        self.keys = chip8.keys;
+        self.length = chip8.cycles;
+    }
+
+    /// Set up `chip8`, with the movie's ROM loaded, to run the way it did when recorded.
+    pub fn prepare(&self, chip8: &mut Chip8State) -> Result<(), MovieError> {
+        if self.rom_hash != chip8.rom_hash {
+            return Err(MovieError::WrongRom {
+                expected: chip8.rom_hash,
+                found: self.rom_hash,
+            });
+        }
+
+        chip8.quirks = self.quirks;
+        chip8.instructions_per_second = self.instructions_per_second;
+        if let Some(source) = self.random.as_deref().and_then(random::parse) {
+            chip8.random = source;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut quirks = self.quirks;
+        let mut names: Vec<&str> = quirk_flags(&mut quirks)
+            .iter()
+            .filter(|(_, set)| **set)
+            .map(|(name, _)| *name)
+            .collect();
+        if let Some(&(name, _)) = LOAD_STORE_NAMES
+            .iter()
+            .find(|(_, increment)| *increment == self.quirks.load_store_increments_i)
+        {
+            names.push(name);
+        }
+
+        let mut text = format!(
+            "{} {}\nrom {:016X}\nquirks {}\nips {}\n",
+            HEADER,
+            VERSION,
+            self.rom_hash,
+            names.join(" "),
+            self.instructions_per_second
+        );
+        if let Some(random) = &self.random {
+            text.push_str(&format!("random {}\n", random));
+        }
+        text.push_str(&format!("length {}\n", self.length));
+        for event in &self.events {
+            let state = if event.pressed { "down" } else { "up" };
+            text.push_str(&format!("{} {:X} {}\n", event.cycle, event.key, state));
+        }
+
+        text
+    }
+
+    /// Parse a movie written by `to_text`. Blank lines and anything after `#` are ignored.
+    pub fn parse(source: &str) -> Result<Movie, MovieError> {
+        let mut lines = source
+            .lines()
+            .enumerate()
+            .map(|(number, line)| (number + 1, line.split('#').next().unwrap_or("")))
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        match lines.next() {
+            Some((line, text)) => match text.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [HEADER, version] => {
+                    let version = version
+                        .parse()
+                        .map_err(|_| parse_error(line, "invalid version"))?;
+                    if version != VERSION {
+                        return Err(MovieError::UnsupportedVersion(version));
+                    }
+                }
+                _ => return Err(parse_error(line, "not a movie")),
+            },
+            None => return Err(parse_error(1, "not a movie")),
+        }
+
+        let mut movie = Movie {
+            rom_hash: 0,
+            quirks: Quirks::default(),
+            instructions_per_second: 0,
+            random: None,
+            length: 0,
+            events: Vec::new(),
+            keys: [false; 16],
+        };
+        let mut seen = Vec::new();
+
+        for (line, text) in lines {
+            let fields: Vec<&str> = text.split_whitespace().collect();
+
+            match fields[0] {
+                "rom" if fields.len() == 2 => {
+                    movie.rom_hash = u64::from_str_radix(fields[1], 16)
+                        .map_err(|_| parse_error(line, "invalid ROM hash"))?;
+                }
+                "quirks" => {
+                    let mut quirks = Quirks {
+                        shift_uses_vy: false,
+                        load_store_increments_i: LoadStoreIncrement::Unchanged,
+                        jump_uses_vx: false,
+                        logic_resets_vf: false,
+                        clip_sprites: false,
+                        wait_for_vblank: false,
+                    };
+                    for name in &fields[1..] {
+                        if let Some(&(_, increment)) =
+                            LOAD_STORE_NAMES.iter().find(|(flag, _)| flag == name)
+                        {
+                            quirks.load_store_increments_i = increment;
+                            continue;
+                        }
+
+                        let mut flags = quirk_flags(&mut quirks);
+                        let flag =
+                            flags
+                                .iter_mut()
+                                .find(|(flag, _)| flag == name)
+                                .ok_or_else(|| {
+                                    parse_error(line, &format!("unknown quirk '{}'", name))
+                                })?;
+                        *flag.1 = true;
+                    }
+                    movie.quirks = quirks;
+                }
+                "ips" if fields.len() == 2 => {
+                    movie.instructions_per_second = parse_number(line, fields[1])?;
+                    if movie.instructions_per_second < MIN_INSTRUCTIONS_PER_SECOND {
+                        return Err(parse_error(
+                            line,
+                            &format!("ips must be at least {}", MIN_INSTRUCTIONS_PER_SECOND),
+                        ));
+                    }
+                }
+                "random" if fields.len() == 2 => {
+                    if random::parse(fields[1]).is_none() {
+                        return Err(parse_error(
+                            line,
+                            &format!("invalid random source '{}'", fields[1]),
+                        ));
+                    }
+                    movie.random = Some(fields[1].to_string());
+                }
+                "length" if fields.len() == 2 => movie.length = parse_number(line, fields[1])?,
+                cycle if cycle.starts_with(|c: char| c.is_ascii_digit()) => {
+                    movie.events.push(parse_event(line, &fields)?);
+                    continue;
+                }
+                _ => return Err(parse_error(line, &format!("unexpected '{}'", text.trim()))),
+            }
+            seen.push(fields[0]);
+        }
+
+        for field in &["rom", "quirks", "ips", "length"] {
+            if !seen.contains(field) {
+                return Err(parse_error(
+                    source.lines().count(),
+                    &format!("missing '{}'", field),
+                ));
+            }
+        }
+        // Stable, so events on the same cycle keep their order.
+        movie.events.sort_by_key(|event| event.cycle);
+
+        Ok(movie)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        fs::write(path, self.to_text())?;
+
+        Ok(())
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Movie, MovieError> {
+        Movie::parse(&fs::read_to_string(path)?)
+    }
+}
+
+/// Replays a movie's key events into a machine.
+#[derive(Debug, Clone)]
+pub struct Player {
+    events: Vec<MovieEvent>,
+    next: usize,
+    length: u64,
+}
+
+impl Player {
+    pub fn new(movie: &Movie) -> Player {
+        Player {
+            events: movie.events.clone(),
+            next: 0,
+            length: movie.length,
+        }
+    }
+
+    /// Apply the events due by `chip8`'s current cycle. Call before every step for an exact
+    /// replay, or before each frame if events were only recorded between frames.
+    pub fn apply(&mut self, chip8: &mut Chip8State) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > chip8.cycles {
+                break;
+            }
+            chip8.keys[event.key as usize] = event.pressed;
+            self.next += 1;
+        }
+    }
+
+    /// Whether the recorded session has been replayed in full.
+    pub fn finished(&self, chip8: &Chip8State) -> bool {
+        self.next == self.events.len() && chip8.cycles >= self.length
+    }
+}
+
+/// Replay `movie` on `chip8`, which must be set up with `Movie::prepare`, one cycle at a time
+/// for its whole length.
+pub fn play(chip8: &mut Chip8State, movie: &Movie) -> Result<(), Chip8Error> {
+    let mut player = Player::new(movie);
+
+    while !player.finished(chip8) {
+        player.apply(chip8);
+        chip8.step()?;
+    }
+
+    Ok(())
+}
+
+fn parse_error(line: usize, message: &str) -> MovieError {
+    MovieError::Parse {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn parse_number<T: FromStr>(line: usize, field: &str) -> Result<T, MovieError> {
+    field
+        .parse()
+        .map_err(|_| parse_error(line, &format!("invalid number '{}'", field)))
+}
+
+fn parse_event(line: usize, fields: &[&str]) -> Result<MovieEvent, MovieError> {
+    if fields.len() != 3 {
+        return Err(parse_error(line, "expected CYCLE KEY down|up"));
+    }
+
+    let cycle = fields[0]
+        .parse()
+        .map_err(|_| parse_error(line, &format!("invalid cycle '{}'", fields[0])))?;
+    let key = u8::from_str_radix(fields[1], 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| parse_error(line, &format!("invalid key '{}'", fields[1])))?;
+    let pressed = match fields[2] {
+        "down" => true,
+        "up" => false,
+        other => {
+            return Err(parse_error(
+                line,
+                &format!("expected down or up, found '{}'", other),
+            ))
+        }
+    };
+
+    Ok(MovieEvent {
+        cycle,
+        key,
+        pressed,
+    })
+}
+
+// How `FX55`/`FX65` move I, by the name used in movie files. Leaving I alone has no name.
+const LOAD_STORE_NAMES: [(&str, LoadStoreIncrement); 2] = [
+    ("load_store_increments_i", LoadStoreIncrement::ByXPlusOne),
+    ("load_store_increments_i_by_x", LoadStoreIncrement::ByX),
+];
+
+// Each on/off quirk by the name used in movie files.
+fn quirk_flags(quirks: &mut Quirks) -> [(&'static str, &mut bool); 5] {
+    [
+        ("shift_uses_vy", &mut quirks.shift_uses_vy),
+        ("jump_uses_vx", &mut quirks.jump_uses_vx),
+        ("logic_resets_vf", &mut quirks.logic_resets_vf),
+        ("clip_sprites", &mut quirks.clip_sprites),
+        ("wait_for_vblank", &mut quirks.wait_for_vblank),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Waits for a key, then draws a random byte's worth of pixels where VX was.
+    const ROM: [u8; 12] = [
+        0xF0, 0x0A, // LD V0, K
+        0xC1, 0xFF, // RND V1, 0xFF
+        0xA3, 0x00, // LD I, 0x300
+        0xF1, 0x33, // LD B, V1
+        0xD0, 0x05, // DRW V0, V0, 5
+        0x12, 0x00, // JP 0x200
+    ];
+
+    fn session(events: &[(u64, u8, bool)], cycles: u64) -> (Chip8State, Movie) {
+        let mut chip8 = Chip8State::with_rom(&ROM).unwrap();
+        let mut movie = Movie::new(&chip8);
+        let mut events = events.iter().peekable();
+
+        while chip8.cycles < cycles {
+            while let Some((_, key, pressed)) =
+                events.next_if(|(cycle, _, _)| *cycle <= chip8.cycles)
+            {
+                chip8.keys[*key as usize] = *pressed;
+            }
+            movie.record(&chip8);
+            chip8.step().unwrap();
+        }
+        movie.record(&chip8);
+
+        (chip8, movie)
+    }
+
+    #[test]
+    fn playback_reproduces_the_session() {
+        let (recorded, movie) = session(&[(10, 5, true), (25, 5, false), (40, 0xA, true)], 100);
+        assert_eq!(movie.events.len(), 3);
+        assert_eq!(movie.length, 100);
+
+        let movie = Movie::parse(&movie.to_text()).unwrap();
+        let mut chip8 = Chip8State::with_rom(&ROM).unwrap();
+        movie.prepare(&mut chip8).unwrap();
+        play(&mut chip8, &movie).unwrap();
+
+        assert_eq!(chip8.cycles, recorded.cycles);
+        assert_eq!(chip8.registers, recorded.registers);
+        assert_eq!(chip8.memory, recorded.memory);
+        assert_eq!(chip8.keys, recorded.keys);
+        assert_eq!(&chip8.video_buf[..], &recorded.video_buf[..]);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let (_, mut movie) = session(&[(3, 0xF, true)], 10);
+        movie.quirks = Quirks::SUPER_CHIP;
+        movie.instructions_per_second = 1000;
+
+        let text = movie.to_text();
+        assert!(text.starts_with("chip8-movie 1\n"));
+        assert!(text.contains("\n3 F down\n"));
+
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed.quirks, Quirks::SUPER_CHIP);
+        assert_eq!(parsed.to_text(), text);
+
+        for &quirks in &[Quirks::COSMAC_VIP, Quirks::CHIP_48, Quirks::MODERN] {
+            movie.quirks = quirks;
+            assert_eq!(Movie::parse(&movie.to_text()).unwrap().quirks, quirks);
+        }
+    }
+
+    #[test]
+    fn rejects_other_roms_and_bad_files() {
+        let (_, movie) = session(&[], 1);
+        let mut chip8 = Chip8State::with_rom(&[0x00, 0xE0]).unwrap();
+        assert!(matches!(
+            movie.prepare(&mut chip8),
+            Err(MovieError::WrongRom { .. })
+        ));
+
+        assert!(matches!(
+            Movie::parse("chip8-movie 2"),
+            Err(MovieError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            Movie::parse("hello"),
+            Err(MovieError::Parse { line: 1, .. })
+        ));
+        let text = movie.to_text().replace("ips 700", "ips 10");
+        assert!(matches!(
+            Movie::parse(&text),
+            Err(MovieError::Parse { line: 4, .. })
+        ));
+        let text = movie.to_text().replace("length", "lenght");
+        assert!(matches!(
+            Movie::parse(&text),
+            Err(MovieError::Parse { line: 6, .. })
+        ));
+    }
+}
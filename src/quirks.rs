@@ -0,0 +1,95 @@
+/// Behaviours that differ between CHIP-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY and store the result in VX, rather than shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// How far `FX55`/`FX65` move I.
+    pub load_store_increments_i: LoadStoreIncrement,
+    /// `BNNN` jumps to `XNN` plus VX instead of `NNN` plus V0.
+    pub jump_uses_vx: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// `DXYN` clips sprites at the screen edges instead of wrapping them around.
+    pub clip_sprites: bool,
+    /// `DXYN` waits for the next 60 Hz vertical blank before drawing.
+    pub wait_for_vblank: bool,
+}
+
+/// How `FX55`/`FX65` change I after storing or loading V0 to VX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStoreIncrement {
+    /// I is left alone.
+    Unchanged,
+    /// I is advanced by X, leaving it on the last register, as CHIP-48 does.
+    ByX,
+    /// I is advanced by X + 1, just past the last register, as the COSMAC VIP does.
+    ByXPlusOne,
+}
+
+impl LoadStoreIncrement {
+    /// How much I moves by for `FX55`/`FX65` with register `x`.
+    pub fn amount(self, x: u8) -> u16 {
+        match self {
+            LoadStoreIncrement::Unchanged => 0,
+            LoadStoreIncrement::ByX => x as u16,
+            LoadStoreIncrement::ByXPlusOne => x as u16 + 1,
+        }
+    }
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: LoadStoreIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        wait_for_vblank: true,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: LoadStoreIncrement::ByX,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        wait_for_vblank: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: LoadStoreIncrement::Unchanged,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        wait_for_vblank: false,
+    };
+
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: LoadStoreIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        wait_for_vblank: false,
+    };
+
+    pub const PRESET_NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "octo"];
+
+    /// Look up a preset by name, e.g. `vip`, `chip48`, `schip` or `octo`.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "octo" | "modern" => Some(Quirks::MODERN),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::COSMAC_VIP
+    }
+}
@@ -0,0 +1,294 @@
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use std::convert::TryInto;
+
+/// Where `CXNN` gets its numbers from.
+pub trait RandomSource: Send {
+    /// The next random byte, before it's masked with `NN`. `memory` is the machine's memory,
+    /// for sources that read the interpreter area like the COSMAC VIP's.
+    fn next_byte(&mut self, memory: &[u8]) -> u8;
+
+    /// Called on every 60 Hz timer tick.
+    fn tick(&mut self) {}
+
+    /// How to create this source as it was when it was created, in the form `parse` accepts,
+    /// or `None` if it can't be recreated.
+    fn spec(&self) -> Option<String> {
+        None
+    }
+
+    /// How far the source has got since it was created, for save states, or `None` if that
+    /// can't be saved.
+    fn position(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Continue from a `position` taken from a source with the same `spec`. Returns `false`
+    /// if the position isn't valid.
+    fn set_position(&mut self, _position: &[u8]) -> bool {
+        false
+    }
+}
+
+/// A pseudorandom generator that gives the same numbers on every run with the same seed.
+///
+/// This is the ChaCha20 generator behind `rand`'s `StdRng`, used directly so its position can
+/// be restored.
+pub struct SeededRandom {
+    seed: u64,
+    rng: ChaCha20Rng,
+    /// 32-bit words drawn so far. Kept here because `ChaCha20Rng::get_word_pos` underflows
+    /// before the first draw.
+    words: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            seed,
+            rng: ChaCha20Rng::seed_from_u64(seed),
+            words: 0,
+        }
+    }
+
+    /// A generator with a seed chosen at random.
+    pub fn from_entropy() -> SeededRandom {
+        SeededRandom::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self, _memory: &[u8]) -> u8 {
+        self.words += 1;
+        self.rng.next_u32() as u8
+    }
+
+    fn spec(&self) -> Option<String> {
+        Some(format!("seed:{}", self.seed))
+    }
+
+    fn position(&self) -> Option<Vec<u8>> {
+        Some(self.words.to_le_bytes().to_vec())
+    }
+
+    fn set_position(&mut self, position: &[u8]) -> bool {
+        match position.try_into() {
+            Ok(position) => {
+                self.words = u64::from_le_bytes(position);
+                self.rng.set_word_pos(self.words as u128);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Returns the given bytes in order, starting again from the first after the last.
+pub struct ScriptedRandom {
+    bytes: Vec<u8>,
+    next: usize,
+}
+
+impl ScriptedRandom {
+    pub fn new(bytes: Vec<u8>) -> ScriptedRandom {
+        ScriptedRandom { bytes, next: 0 }
+    }
+}
+
+impl RandomSource for ScriptedRandom {
+    fn next_byte(&mut self, _memory: &[u8]) -> u8 {
+        if self.bytes.is_empty() {
+            return 0;
+        }
+
+        let byte = self.bytes[self.next];
+        self.next = (self.next + 1) % self.bytes.len();
+        byte
+    }
+
+    fn spec(&self) -> Option<String> {
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        Some(format!("script:{}", bytes.join(",")))
+    }
+
+    fn position(&self) -> Option<Vec<u8>> {
+        Some((self.next as u32).to_le_bytes().to_vec())
+    }
+
+    fn set_position(&mut self, position: &[u8]) -> bool {
+        let next = match position.try_into() {
+            Ok(position) => u32::from_le_bytes(position) as usize,
+            Err(_) => return false,
+        };
+        // An empty script stays at 0.
+        if next >= self.bytes.len().max(1) {
+            return false;
+        }
+
+        self.next = next;
+        true
+    }
+}
+
+/// The COSMAC VIP interpreter's routine, which keeps a 16-bit seed in register R9 and bumps it
+/// on every 60 Hz interrupt, so numbers depend on timing. Each `CXNN` increments R9, adds the
+/// byte that R9's low half points to in the interpreter's page to its high half, and returns
+/// the high half.
+///
+/// The VIP reads its own interpreter code there; this emulator reads the font data it keeps in
+/// the same place, so the numbers differ from a real VIP's but behave the same way.
+#[derive(Default)]
+pub struct VipRandom {
+    r9: u16,
+}
+
+impl VipRandom {
+    pub fn new() -> VipRandom {
+        VipRandom::default()
+    }
+}
+
+impl RandomSource for VipRandom {
+    fn next_byte(&mut self, memory: &[u8]) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+
+        let [low, high] = self.r9.to_le_bytes();
+        let high = high.wrapping_add(memory.get(low as usize).copied().unwrap_or(0));
+        self.r9 = u16::from_le_bytes([low, high]);
+
+        high
+    }
+
+    fn tick(&mut self) {
+        self.r9 = self.r9.wrapping_add(1);
+    }
+
+    fn spec(&self) -> Option<String> {
+        Some("vip".to_string())
+    }
+
+    fn position(&self) -> Option<Vec<u8>> {
+        Some(self.r9.to_le_bytes().to_vec())
+    }
+
+    fn set_position(&mut self, position: &[u8]) -> bool {
+        match position.try_into() {
+            Ok(position) => {
+                self.r9 = u16::from_le_bytes(position);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Create a source from `seed:N` (or just `N`), `vip`, or `script:` followed by comma
+/// separated hex bytes.
+pub fn parse(spec: &str) -> Option<Box<dyn RandomSource>> {
+    let (kind, value) = match spec.find(':') {
+        Some(colon) => (&spec[..colon], Some(&spec[colon + 1..])),
+        None => (spec, None),
+    };
+
+    match (kind.to_ascii_lowercase().as_str(), value) {
+        ("seed", Some(seed)) => Some(Box::new(SeededRandom::new(seed.parse().ok()?))),
+        ("vip" | "cosmac", None) => Some(Box::new(VipRandom::new())),
+        ("script", Some(bytes)) => {
+            let bytes = bytes
+                .split(',')
+                .map(|byte| u8::from_str_radix(byte.trim(), 16).ok())
+                .collect::<Option<Vec<u8>>>()?;
+            Some(Box::new(ScriptedRandom::new(bytes)))
+        }
+        (seed, None) => Some(Box::new(SeededRandom::new(seed.parse().ok()?))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_covers_every_byte() {
+        let mut random = SeededRandom::new(7);
+        let mut seen = [false; 256];
+        for _ in 0..10_000 {
+            seen[random.next_byte(&[]) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn scripted_repeats() {
+        let mut random = ScriptedRandom::new(vec![1, 0xFF]);
+        let bytes: Vec<u8> = (0..5).map(|_| random.next_byte(&[])).collect();
+        assert_eq!(bytes, [1, 0xFF, 1, 0xFF, 1]);
+    }
+
+    #[test]
+    fn vip_depends_on_ticks() {
+        let memory: Vec<u8> = (0..=255).collect();
+
+        let mut first = VipRandom::new();
+        let mut second = VipRandom::new();
+        second.tick();
+        // R9 goes to 0x0001 and adds memory[0x01], then to 0x0102 adding memory[0x02].
+        assert_eq!(first.next_byte(&memory), 0x01);
+        assert_eq!(first.next_byte(&memory), 0x03);
+        assert_eq!(second.next_byte(&memory), 0x02);
+    }
+
+    #[test]
+    fn seeded_matches_std_rng() {
+        use rand::rngs::StdRng;
+        use rand::Rng;
+
+        let mut random = SeededRandom::new(3);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..100 {
+            assert_eq!(random.next_byte(&[]), rng.gen::<u8>());
+        }
+    }
+
+    #[test]
+    fn positions_restore() {
+        for spec in &["seed:5", "vip", "script:01,02,03"] {
+            let memory: Vec<u8> = (0..=255).collect();
+            let mut random = parse(spec).unwrap();
+            for _ in 0..37 {
+                random.next_byte(&memory);
+            }
+
+            let mut restored = parse(spec).unwrap();
+            assert!(restored.set_position(&random.position().unwrap()));
+            for _ in 0..20 {
+                assert_eq!(restored.next_byte(&memory), random.next_byte(&memory));
+            }
+            assert!(!restored.set_position(&[1]));
+        }
+
+        assert!(!parse("script:01")
+            .unwrap()
+            .set_position(&1u32.to_le_bytes()));
+    }
+
+    #[test]
+    fn specs_round_trip() {
+        for spec in &["seed:42", "vip", "script:00,7F,FF"] {
+            assert_eq!(parse(spec).unwrap().spec().as_deref(), Some(*spec));
+        }
+        assert_eq!(parse("42").unwrap().spec().as_deref(), Some("seed:42"));
+        assert!(parse("script:GG").is_none());
+        assert!(parse("dice").is_none());
+    }
+}
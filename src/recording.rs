@@ -0,0 +1,251 @@
+use crate::chip8::{Chip8State, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, TIMER_HZ};
+use crate::screenshot::{self, Image, Palette};
+
+use gif::SetParameter;
+
+use std::fs::{self, File};
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// Numbered PNG files in a directory.
+    Frames(PathBuf),
+}
+
+/// Records the screen once per frame into an animated GIF or a sequence of PNG files.
+///
+/// Frames are the size of the high resolution screen, so low resolution pixels are doubled and
+/// switching resolution doesn't change the size. A frame identical to the one before isn't
+/// stored again; in a GIF the earlier frame is shown for longer, and in a sequence that frame
+/// number is skipped.
+pub struct Recorder {
+    output: Output,
+    scale: u32,
+    palette: Palette,
+    /// Frames recorded so far, including duplicates.
+    frames: u64,
+    /// The last distinct frame and the frame number it first appeared on, not yet written.
+    pending: Option<(u64, Vec<u8>)>,
+    /// Frame number up to which GIF delays have been written.
+    written: u64,
+}
+
+impl Recorder {
+    /// Record an animated GIF at `path`, with each high resolution pixel `scale` pixels wide.
+    pub fn gif<P: AsRef<Path>>(path: P, scale: u32, palette: &Palette) -> io::Result<Recorder> {
+        let scale = scale.max(1);
+        let (width, height) = frame_size(scale);
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "scale is too large for a GIF",
+            ));
+        }
+
+        let file = BufWriter::new(File::create(path)?);
+        let colours: Vec<u8> = palette.iter().flatten().copied().collect();
+        let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &colours)?;
+        encoder.set(gif::Repeat::Infinite)?;
+
+        Ok(Recorder::new(Output::Gif(encoder), scale, palette))
+    }
+
+    /// Record numbered PNG files such as `frame-000042.png` into the directory `dir`, which is
+    /// created if needed. The number is the frame each image first appeared on.
+    pub fn frames<P: AsRef<Path>>(dir: P, scale: u32, palette: &Palette) -> io::Result<Recorder> {
+        fs::create_dir_all(&dir)?;
+        let output = Output::Frames(dir.as_ref().to_path_buf());
+
+        Ok(Recorder::new(output, scale.max(1), palette))
+    }
+
+    /// Record into `path`, as a GIF if it ends in `.gif` or otherwise a directory of frames.
+    pub fn create<P: AsRef<Path>>(path: P, scale: u32, palette: &Palette) -> io::Result<Recorder> {
+        let is_gif = path
+            .as_ref()
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+
+        if is_gif {
+            Recorder::gif(path, scale, palette)
+        } else {
+            Recorder::frames(path, scale, palette)
+        }
+    }
+
+    fn new(output: Output, scale: u32, palette: &Palette) -> Recorder {
+        Recorder {
+            output,
+            scale,
+            palette: *palette,
+            frames: 0,
+            pending: None,
+            written: 0,
+        }
+    }
+
+    /// Frames recorded so far, at 60 per second.
+    pub fn frames_recorded(&self) -> u64 {
+        self.frames
+    }
+
+    /// Record the current screen. Call once per 60 Hz frame.
+    pub fn frame(&mut self, chip8: &Chip8State) -> io::Result<()> {
+        let pixels = screen_pixels(chip8);
+        let frame = self.frames;
+        self.frames += 1;
+
+        match &self.pending {
+            Some((_, pending)) if *pending == pixels => Ok(()),
+            _ => {
+                if let Some((start, pending)) = self.pending.take() {
+                    self.write(start, frame, &pending)?;
+                }
+                self.pending = Some((frame, pixels));
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the last frame and finish the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some((start, pending)) = self.pending.take() {
+            self.write(start, self.frames, &pending)?;
+        }
+
+        // The GIF trailer is written when the encoder is dropped.
+        Ok(())
+    }
+
+    // Write a frame shown from frame `start` until just before `end`.
+    fn write(&mut self, start: u64, end: u64, pixels: &[u8]) -> io::Result<()> {
+        let scaled = scale_pixels(pixels, self.scale);
+        let (width, height) = frame_size(self.scale);
+        let palette = &self.palette;
+
+        match &mut self.output {
+            Output::Gif(encoder) => {
+                // Delays are in hundredths of a second, so round the end time rather than each
+                // delay to keep the total in step with 60 fps.
+                let centiseconds =
+                    |frame: u64| (frame * 100 + TIMER_HZ as u64 / 2) / TIMER_HZ as u64;
+                let delay = centiseconds(end) - centiseconds(self.written);
+                self.written = end;
+
+                let mut frame =
+                    gif::Frame::from_indexed_pixels(width as u16, height as u16, &scaled, None);
+                frame.delay = delay.min(u16::MAX as u64) as u16;
+                encoder.write_frame(&frame)
+            }
+            Output::Frames(dir) => {
+                let image = Image {
+                    width,
+                    height,
+                    data: scaled
+                        .iter()
+                        .flat_map(|&pixel| palette[pixel as usize].iter().copied())
+                        .collect(),
+                };
+                let path = dir.join(format!("frame-{:06}.png", start));
+                screenshot::write_png_image(&image, path)
+            }
+        }
+    }
+}
+
+fn frame_size(scale: u32) -> (u32, u32) {
+    (
+        HIRES_SCREEN_WIDTH as u32 * scale,
+        HIRES_SCREEN_HEIGHT as u32 * scale,
+    )
+}
+
+// The screen as palette indices at high resolution.
+fn screen_pixels(chip8: &Chip8State) -> Vec<u8> {
+    let size = HIRES_SCREEN_WIDTH / chip8.screen_width();
+    let mut pixels = Vec::with_capacity(HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT);
+
+    for row in 0..HIRES_SCREEN_HEIGHT {
+        let row = &chip8.video_buf[row / size];
+        for col in 0..HIRES_SCREEN_WIDTH {
+            pixels.push(row[col / size]);
+        }
+    }
+
+    pixels
+}
+
+fn scale_pixels(pixels: &[u8], scale: u32) -> Vec<u8> {
+    let scale = scale as usize;
+    let mut scaled = Vec::with_capacity(pixels.len() * scale * scale);
+
+    for row in pixels.chunks(HIRES_SCREEN_WIDTH) {
+        let start = scaled.len();
+        for &pixel in row {
+            for _ in 0..scale {
+                scaled.push(pixel);
+            }
+        }
+        for _ in 1..scale {
+            scaled.extend_from_within(start..start + HIRES_SCREEN_WIDTH * scale);
+        }
+    }
+
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chip8::PALETTE;
+
+    use std::env;
+
+    #[test]
+    fn identical_frames_are_written_once() {
+        let dir = env::temp_dir().join(format!("chip8-recording-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut chip8 = Chip8State::new();
+        let mut recorder = Recorder::frames(&dir, 1, &PALETTE).unwrap();
+        for frame in 0..10 {
+            if frame == 4 {
+                chip8.video_buf[0][0] = 1;
+            }
+            recorder.frame(&chip8).unwrap();
+        }
+        assert_eq!(recorder.frames_recorded(), 10);
+        recorder.finish().unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["frame-000000.png", "frame-000004.png"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn low_resolution_pixels_are_doubled() {
+        let mut chip8 = Chip8State::new();
+        chip8.video_buf[0][1] = 1;
+
+        let pixels = screen_pixels(&chip8);
+        assert_eq!(pixels.len(), HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT);
+        assert_eq!(&pixels[..4], [0, 0, 1, 1]);
+        assert_eq!(
+            &pixels[HIRES_SCREEN_WIDTH..HIRES_SCREEN_WIDTH + 4],
+            [0, 0, 1, 1]
+        );
+        assert_eq!(pixels[2 * HIRES_SCREEN_WIDTH + 2], 0);
+
+        let scaled = scale_pixels(&pixels, 2);
+        assert_eq!(scaled.len(), pixels.len() * 4);
+        assert_eq!(scaled[3 * HIRES_SCREEN_WIDTH * 2 + 4], 1);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::chip8::Chip8State;
+use crate::savestate::{self, SaveStateError};
+
+use std::collections::VecDeque;
+
+/// How much history to keep for rewinding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Frames between snapshots. Each rewound snapshot jumps back this many frames.
+    pub interval: u32,
+    /// The most snapshots to keep.
+    pub depth: usize,
+    /// The most bytes of compressed history to keep, not counting the newest snapshot.
+    pub max_bytes: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> RewindConfig {
+        RewindConfig {
+            interval: 1,
+            depth: 60 * 60,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// A bounded history of snapshots of the machine.
+///
+/// Only the newest snapshot is stored in full. Each older one is kept as the difference from
+/// the snapshot after it, and since only a few bytes change from frame to frame these deltas
+/// are tiny. When the history is full the oldest delta is simply dropped.
+pub struct Rewind {
+    config: RewindConfig,
+    frames: u32,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Rewind {
+        Rewind {
+            config,
+            frames: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    /// Number of snapshots that can currently be rewound to.
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes used by the history, including the newest snapshot.
+    pub fn memory_used(&self) -> usize {
+        self.bytes + self.newest.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.deltas.clear();
+        self.bytes = 0;
+    }
+
+    /// Call once per frame of emulation. Takes a snapshot every `interval` frames.
+    pub fn frame(&mut self, chip8: &Chip8State) {
+        self.frames += 1;
+        if self.frames >= self.config.interval.max(1) {
+            self.frames = 0;
+            self.push(chip8);
+        }
+    }
+
+    pub fn push(&mut self, chip8: &Chip8State) {
+        let snapshot = savestate::save(chip8);
+
+        if let Some(newest) = self.newest.take() {
+            if newest.len() == snapshot.len() {
+                let delta = encode_delta(&snapshot, &newest);
+                self.bytes += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                self.clear();
+            }
+        }
+        self.newest = Some(snapshot);
+
+        while self.deltas.len() + 1 > self.config.depth.max(1) || self.bytes > self.config.max_bytes
+        {
+            match self.deltas.pop_front() {
+                Some(delta) => self.bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restore the newest snapshot and drop it from the history, so the next call goes further
+    /// back. The oldest snapshot is never dropped, so holding rewind stops there.
+    ///
+    /// The keys currently held are kept rather than restored. Returns `false` if there is no
+    /// history. If a snapshot can't be loaded, for example because another ROM was loaded
+    /// since it was taken, the history is cleared.
+    pub fn rewind(&mut self, chip8: &mut Chip8State) -> Result<bool, SaveStateError> {
+        let newest = match self.newest.as_mut() {
+            Some(newest) => newest,
+            None => return Ok(false),
+        };
+
+        let keys = chip8.keys;
+        if let Err(err) = savestate::load(chip8, newest) {
+            self.clear();
+            return Err(err);
+        }
+        chip8.keys = keys;
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.bytes -= delta.len();
+            apply_delta(newest, &delta);
+        }
+        self.frames = 0;
+
+        Ok(true)
+    }
+}
+
+// The XOR of two equal-length snapshots, run-length encoded as pairs of an unchanged run
+// length and a changed run length, each followed by the changed bytes.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut pos = 0;
+
+    while pos < from.len() {
+        let unchanged = from[pos..]
+            .iter()
+            .zip(&to[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        pos += unchanged;
+        if pos == from.len() {
+            break;
+        }
+
+        let changed = from[pos..]
+            .iter()
+            .zip(&to[pos..])
+            .take_while(|(a, b)| a != b)
+            .count();
+
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend(
+            from[pos..pos + changed]
+                .iter()
+                .zip(&to[pos..])
+                .map(|(a, b)| a ^ b),
+        );
+        pos += changed;
+    }
+
+    delta
+}
+
+fn apply_delta(snapshot: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut cursor = 0;
+
+    while cursor < delta.len() {
+        pos += read_varint(delta, &mut cursor);
+        let changed = read_varint(delta, &mut cursor);
+        for (byte, diff) in snapshot[pos..pos + changed]
+            .iter_mut()
+            .zip(&delta[cursor..cursor + changed])
+        {
+            *byte ^= diff;
+        }
+        pos += changed;
+        cursor += changed;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], cursor: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*cursor];
+        *cursor += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ADD V0, 1; RND V1, 0xFF; LD I, 0x300; LD [I], V1; JP 0x200
+    const ROM: [u8; 10] = [0x70, 0x01, 0xC1, 0xFF, 0xA3, 0x00, 0xF1, 0x55, 0x12, 0x00];
+
+    fn machine() -> Chip8State {
+        let mut chip8 = Chip8State::with_rom(&ROM).unwrap();
+        chip8.seed_random(1);
+        chip8
+    }
+
+    // Run `frames` frames, pushing and returning a snapshot after each.
+    fn record(rewind: &mut Rewind, chip8: &mut Chip8State, frames: usize) -> Vec<Vec<u8>> {
+        (0..frames)
+            .map(|_| {
+                chip8.run_frame().unwrap();
+                rewind.push(chip8);
+                savestate::save(chip8)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let from: Vec<u8> = (0..1000).map(|i| (i * 7 % 251) as u8).collect();
+        let mut to = from.clone();
+        to[0] ^= 1;
+        for byte in &mut to[300..600] {
+            *byte = !*byte;
+        }
+        to[999] = 0;
+
+        let mut snapshot = to.clone();
+        apply_delta(&mut snapshot, &encode_delta(&from, &to));
+        assert_eq!(snapshot, from);
+        assert!(encode_delta(&from, &from).is_empty());
+    }
+
+    #[test]
+    fn rewinds_every_frame() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        let snapshots = record(&mut rewind, &mut chip8, 100);
+        assert_eq!(rewind.len(), 100);
+
+        for snapshot in snapshots.iter().rev() {
+            assert!(rewind.rewind(&mut chip8).unwrap());
+            assert_eq!(&savestate::save(&chip8), snapshot);
+        }
+
+        // The oldest snapshot stays.
+        assert!(rewind.rewind(&mut chip8).unwrap());
+        assert_eq!(savestate::save(&chip8), snapshots[0]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn depth_limits_history() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(RewindConfig {
+            depth: 5,
+            ..RewindConfig::default()
+        });
+        let snapshots = record(&mut rewind, &mut chip8, 20);
+        assert_eq!(rewind.len(), 5);
+
+        for _ in 0..10 {
+            rewind.rewind(&mut chip8).unwrap();
+        }
+        assert_eq!(savestate::save(&chip8), snapshots[15]);
+    }
+
+    #[test]
+    fn memory_cap_limits_history() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(RewindConfig {
+            max_bytes: 200,
+            ..RewindConfig::default()
+        });
+        let snapshots = record(&mut rewind, &mut chip8, 100);
+        let newest = snapshots[99].len();
+
+        assert!(rewind.len() > 1 && rewind.len() < 100);
+        assert!(rewind.memory_used() - newest <= 200);
+
+        let kept = rewind.len();
+        for _ in 0..kept {
+            rewind.rewind(&mut chip8).unwrap();
+        }
+        assert_eq!(savestate::save(&chip8), snapshots[100 - kept]);
+    }
+
+    #[test]
+    fn snapshots_of_another_rom_clear_the_history() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        record(&mut rewind, &mut chip8, 3);
+
+        let mut other = Chip8State::with_rom(&[0x12, 0x00]).unwrap();
+        assert!(matches!(
+            rewind.rewind(&mut other),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+        assert!(rewind.is_empty());
+        assert!(!rewind.rewind(&mut other).unwrap());
+    }
+}
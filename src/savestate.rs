@@ -0,0 +1,557 @@
+use crate::chip8::{
+    Chip8State, AUDIO_PATTERN_SIZE, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH,
+    MIN_INSTRUCTIONS_PER_SECOND, PLANES,
+};
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::random;
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"CH8S";
+pub const VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    NotASaveState,
+    UnsupportedVersion(u16),
+    WrongRom {
+        expected: u64,
+        found: u64,
+    },
+    Truncated,
+    /// A field holds a value the machine can't be in.
+    Invalid(&'static str),
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            SaveStateError::WrongRom { expected, found } => write!(
+                f,
+                "save state is for a different ROM (hash {:016X}, loaded ROM is {:016X})",
+                found, expected
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+            SaveStateError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for SaveStateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaveStateError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveStateError {
+    fn from(err: io::Error) -> SaveStateError {
+        SaveStateError::Io(err)
+    }
+}
+
+/// Serialise the whole machine, including its quirks, clock rate and random source.
+///
+/// The layout is the magic bytes `CH8S`, a little-endian `u16` version and the ROM hash,
+/// followed by every field of the state in declaration order.
+pub fn save(chip8: &Chip8State) -> Vec<u8> {
+    let mut out =
+        Vec::with_capacity(chip8.memory.len() + HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT + 256);
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&chip8.rom_hash.to_le_bytes());
+
+    out.extend_from_slice(&(chip8.memory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&chip8.memory);
+    out.extend_from_slice(&chip8.pc.to_le_bytes());
+    out.extend_from_slice(&chip8.registers);
+    out.extend_from_slice(&chip8.i_register.to_le_bytes());
+    for address in &chip8.stack {
+        out.extend_from_slice(&address.to_le_bytes());
+    }
+    out.push(chip8.sp);
+    out.push(chip8.delay_timer);
+    out.push(chip8.sound_timer);
+    out.extend(chip8.keys.iter().map(|&key| key as u8));
+    for row in chip8.video_buf.iter() {
+        out.extend_from_slice(row);
+    }
+    out.push(chip8.hires as u8);
+    out.push(chip8.planes);
+    out.extend_from_slice(&chip8.audio_pattern);
+    out.push(chip8.pitch);
+    out.push(chip8.halted as u8);
+    out.extend_from_slice(&chip8.rpl_flags);
+    out.extend_from_slice(&chip8.instructions_per_second.to_le_bytes());
+    out.extend_from_slice(&chip8.cycles.to_le_bytes());
+    out.push(quirk_flags(&chip8.quirks));
+    out.extend_from_slice(&chip8.timer_phase.to_le_bytes());
+    out.push(chip8.vblank as u8);
+
+    // The random source as `random::parse` accepts it, then its position, both empty if it
+    // can't be saved.
+    let (spec, position) = match (chip8.random.spec(), chip8.random.position()) {
+        (Some(spec), Some(position)) => (spec.into_bytes(), position),
+        _ => (Vec::new(), Vec::new()),
+    };
+    for field in &[spec, position] {
+        out.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        out.extend_from_slice(field);
+    }
+
+    out
+}
+
+/// Restore a state written by `save`, which must be for the ROM currently loaded in `chip8`.
+///
+/// `chip8` is left untouched if the state is rejected.
+pub fn load(chip8: &mut Chip8State, data: &[u8]) -> Result<(), SaveStateError> {
+    let mut reader = Reader { data, pos: 0 };
+
+    if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let hash = reader.u64()?;
+    if hash != chip8.rom_hash {
+        return Err(SaveStateError::WrongRom {
+            expected: chip8.rom_hash,
+            found: hash,
+        });
+    }
+
+    let mut state = Chip8State::new();
+    state.rom_hash = hash;
+
+    let memory_size = reader.u32()? as usize;
+    if memory_size != state.memory.len() {
+        return Err(SaveStateError::Invalid("memory size"));
+    }
+    state.memory = reader.bytes(memory_size)?.to_vec();
+    state.pc = reader.u16()?;
+    state.registers.copy_from_slice(reader.bytes(16)?);
+    state.i_register = reader.u16()?;
+    for address in state.stack.iter_mut() {
+        *address = reader.u16()?;
+    }
+    state.sp = reader.u8()?;
+    if state.sp as usize > state.stack.len() {
+        return Err(SaveStateError::Invalid("stack pointer"));
+    }
+    state.delay_timer = reader.u8()?;
+    state.sound_timer = reader.u8()?;
+    for key in state.keys.iter_mut() {
+        *key = reader.u8()? != 0;
+    }
+    for row in state.video_buf.iter_mut() {
+        row.copy_from_slice(reader.bytes(HIRES_SCREEN_WIDTH)?);
+    }
+    if state
+        .video_buf
+        .iter()
+        .flatten()
+        .any(|&pixel| pixel >= 1 << PLANES)
+    {
+        return Err(SaveStateError::Invalid("pixel"));
+    }
+    state.hires = reader.u8()? != 0;
+    state.planes = reader.u8()?;
+    if state.planes >= 1 << PLANES {
+        return Err(SaveStateError::Invalid("plane selection"));
+    }
+    state
+        .audio_pattern
+        .copy_from_slice(reader.bytes(AUDIO_PATTERN_SIZE)?);
+    state.pitch = reader.u8()?;
+    state.halted = reader.u8()? != 0;
+    state.rpl_flags.copy_from_slice(reader.bytes(16)?);
+    state.instructions_per_second = reader.u32()?;
+    if state.instructions_per_second < MIN_INSTRUCTIONS_PER_SECOND {
+        return Err(SaveStateError::Invalid("clock rate"));
+    }
+    state.cycles = reader.u64()?;
+    state.quirks = quirks_from_flags(reader.u8()?);
+    state.timer_phase = reader.u32()?;
+    state.vblank = reader.u8()? != 0;
+
+    let spec = reader.sized()?;
+    let position = reader.sized()?;
+    if spec.is_empty() {
+        // The source couldn't be saved, so keep the one in use.
+        mem::swap(&mut state.random, &mut chip8.random);
+    } else {
+        let mut source = std::str::from_utf8(spec)
+            .ok()
+            .and_then(random::parse)
+            .ok_or(SaveStateError::Invalid("random source"))?;
+        if !source.set_position(position) {
+            return Err(SaveStateError::Invalid("random source position"));
+        }
+        state.random = source;
+    }
+
+    // Tracing and access recording belong to the session rather than the machine.
+    state.tracer = chip8.tracer.take();
+    state.record_accesses = chip8.record_accesses;
+    *chip8 = state;
+
+    Ok(())
+}
+
+pub fn save_file<P: AsRef<Path>>(chip8: &Chip8State, path: P) -> Result<(), SaveStateError> {
+    fs::write(path, save(chip8))?;
+
+    Ok(())
+}
+
+pub fn load_file<P: AsRef<Path>>(chip8: &mut Chip8State, path: P) -> Result<(), SaveStateError> {
+    let data = fs::read(path)?;
+
+    load(chip8, &data)
+}
+
+/// The file for a numbered save slot, kept next to the ROM, e.g. `pong.state1`.
+pub fn slot_path<P: AsRef<Path>>(rom: P, slot: u8) -> PathBuf {
+    rom.as_ref().with_extension(format!("state{}", slot))
+}
+
+// Bit 1 is `FX55`/`FX65` advancing I by X + 1 and bit 6 by X.
+fn quirk_flags(quirks: &Quirks) -> u8 {
+    [
+        quirks.shift_uses_vy,
+        quirks.load_store_increments_i == LoadStoreIncrement::ByXPlusOne,
+        quirks.jump_uses_vx,
+        quirks.logic_resets_vf,
+        quirks.clip_sprites,
+        quirks.wait_for_vblank,
+        quirks.load_store_increments_i == LoadStoreIncrement::ByX,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |flags, (bit, &set)| flags | (set as u8) << bit)
+}
+
+fn quirks_from_flags(flags: u8) -> Quirks {
+    let flag = |bit: u8| flags & (1 << bit) != 0;
+
+    Quirks {
+        shift_uses_vy: flag(0),
+        load_store_increments_i: match (flag(1), flag(6)) {
+            (true, _) => LoadStoreIncrement::ByXPlusOne,
+            (false, true) => LoadStoreIncrement::ByX,
+            (false, false) => LoadStoreIncrement::Unchanged,
+        },
+        jump_uses_vx: flag(2),
+        logic_resets_vf: flag(3),
+        clip_sprites: flag(4),
+        wait_for_vblank: flag(5),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self
+            .pos
+            .checked_add(count)
+            .ok_or(SaveStateError::Truncated)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(SaveStateError::Truncated)?;
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Bytes preceded by their length as a `u16`.
+    fn sized(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let length = self.u16()? as usize;
+        self.bytes(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chip8::MEMORY_SIZE;
+    use crate::random::RandomSource;
+    use crate::trace::{TraceFormat, Tracer};
+
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    const ROM: [u8; 4] = [0x60, 0x05, 0x12, 0x02];
+
+    // Offsets of fields in a saved state.
+    const VERSION_OFFSET: usize = 4;
+    const MEMORY_SIZE_OFFSET: usize = 14;
+    const SP_OFFSET: usize = MEMORY_SIZE_OFFSET + 4 + MEMORY_SIZE + 2 + 16 + 2 + 12 * 2;
+
+    fn machine() -> Chip8State {
+        Chip8State::with_rom(&ROM).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut chip8 = machine();
+        chip8.run_frame().unwrap();
+        chip8.registers[0xA] = 0x42;
+        chip8.i_register = 0x345;
+        chip8.stack[0] = 0x208;
+        chip8.sp = 1;
+        chip8.delay_timer = 9;
+        chip8.keys[3] = true;
+        chip8.video_buf[5][7] = 3;
+        chip8.hires = true;
+        chip8.planes = 2;
+        chip8.quirks = Quirks::SUPER_CHIP;
+        chip8.memory[0xFFFF] = 0x99;
+
+        let mut loaded = machine();
+        load(&mut loaded, &save(&chip8)).unwrap();
+
+        assert_eq!(loaded.memory, chip8.memory);
+        assert_eq!(loaded.pc, chip8.pc);
+        assert_eq!(loaded.registers, chip8.registers);
+        assert_eq!(loaded.i_register, 0x345);
+        assert_eq!((loaded.stack, loaded.sp), (chip8.stack, 1));
+        assert_eq!(loaded.delay_timer, 9);
+        assert_eq!(loaded.keys, chip8.keys);
+        assert_eq!(&loaded.video_buf[..], &chip8.video_buf[..]);
+        assert_eq!((loaded.hires, loaded.planes), (true, 2));
+        assert_eq!(loaded.quirks, Quirks::SUPER_CHIP);
+        assert_eq!(loaded.cycles, chip8.cycles);
+        assert_eq!(save(&loaded), save(&chip8));
+
+        for &quirks in &[Quirks::COSMAC_VIP, Quirks::CHIP_48, Quirks::MODERN] {
+            chip8.quirks = quirks;
+            load(&mut loaded, &save(&chip8)).unwrap();
+            assert_eq!(loaded.quirks, quirks);
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn loading_keeps_the_session() {
+        let out = Shared::default();
+        let mut chip8 = machine();
+        chip8.tracer = Some(Tracer::new(Box::new(out.clone()), TraceFormat::Text));
+        chip8.record_accesses = true;
+
+        let data = save(&chip8);
+        chip8.step().unwrap();
+        load(&mut chip8, &data).unwrap();
+        chip8.step().unwrap();
+
+        assert_eq!(chip8.tracer.as_ref().map(Tracer::lines), Some(2));
+        assert!(chip8.record_accesses);
+        let trace = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            trace.lines().filter(|line| line.contains("0x200")).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let data = save(&machine());
+        let mut other = Chip8State::with_rom(&[0x00, 0xE0]).unwrap();
+        assert!(matches!(
+            load(&mut other, &data),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let data = save(&machine());
+        let mut chip8 = machine();
+
+        assert!(matches!(
+            load(&mut chip8, b"PNG!"),
+            Err(SaveStateError::NotASaveState)
+        ));
+        assert!(matches!(
+            load(&mut chip8, &data[..data.len() - 1]),
+            Err(SaveStateError::Truncated)
+        ));
+
+        for &version in &[VERSION - 1, VERSION + 1] {
+            let mut other = data.clone();
+            other[VERSION_OFFSET..VERSION_OFFSET + 2].copy_from_slice(&version.to_le_bytes());
+            assert!(matches!(
+                load(&mut chip8, &other),
+                Err(SaveStateError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_impossible_values() {
+        let data = save(&machine());
+        let mut chip8 = machine();
+        chip8.registers[0] = 0x77;
+
+        let mut small = data.clone();
+        small[MEMORY_SIZE_OFFSET..MEMORY_SIZE_OFFSET + 4].copy_from_slice(&16u32.to_le_bytes());
+        assert!(matches!(
+            load(&mut chip8, &small),
+            Err(SaveStateError::Invalid("memory size"))
+        ));
+
+        let mut deep = data.clone();
+        deep[SP_OFFSET] = 13;
+        assert!(matches!(
+            load(&mut chip8, &deep),
+            Err(SaveStateError::Invalid("stack pointer"))
+        ));
+
+        let mut planes = machine();
+        planes.planes = 4;
+        assert!(matches!(
+            load(&mut chip8, &save(&planes)),
+            Err(SaveStateError::Invalid("plane selection"))
+        ));
+
+        let mut pixels = machine();
+        pixels.video_buf[0][0] = 4;
+        assert!(matches!(
+            load(&mut chip8, &save(&pixels)),
+            Err(SaveStateError::Invalid("pixel"))
+        ));
+
+        let mut script = machine();
+        script.random = random::parse("script:01,02").unwrap();
+        let mut past_the_end = save(&script);
+        let end = past_the_end.len();
+        past_the_end[end - 4..].copy_from_slice(&5u32.to_le_bytes());
+        assert!(matches!(
+            load(&mut chip8, &past_the_end),
+            Err(SaveStateError::Invalid("random source position"))
+        ));
+
+        let mut unknown = machine();
+        unknown.random = Box::new(Custom(Some("dice".to_string())));
+        assert!(matches!(
+            load(&mut chip8, &save(&unknown)),
+            Err(SaveStateError::Invalid("random source"))
+        ));
+
+        // Rejected states leave the machine alone.
+        assert_eq!(chip8.registers[0], 0x77);
+    }
+
+    // A source that always gives 7 and claims to have `spec`.
+    struct Custom(Option<String>);
+
+    impl RandomSource for Custom {
+        fn next_byte(&mut self, _memory: &[u8]) -> u8 {
+            7
+        }
+
+        fn spec(&self) -> Option<String> {
+            self.0.clone()
+        }
+
+        fn position(&self) -> Option<Vec<u8>> {
+            Some(Vec::new())
+        }
+    }
+
+    // RND V0, 0xFF; JP 0x200
+    const RND: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    fn draws(chip8: &mut Chip8State, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                chip8.step().unwrap();
+                chip8.step().unwrap();
+                chip8.registers[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_sources_continue_from_the_save() {
+        for spec in &["seed:42", "vip", "script:01,02,03"] {
+            let mut chip8 = Chip8State::with_rom(&RND).unwrap();
+            chip8.random = random::parse(spec).unwrap();
+            draws(&mut chip8, 5);
+
+            let data = save(&chip8);
+            let expected = draws(&mut chip8, 10);
+
+            let mut loaded = Chip8State::with_rom(&RND).unwrap();
+            loaded.seed_random(7);
+            load(&mut loaded, &data).unwrap();
+            assert_eq!(loaded.random.spec().as_deref(), Some(*spec));
+            assert_eq!(draws(&mut loaded, 10), expected, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn unsaveable_sources_are_kept() {
+        let mut chip8 = Chip8State::with_rom(&RND).unwrap();
+        chip8.random = Box::new(Custom(None));
+        let data = save(&chip8);
+
+        let mut loaded = Chip8State::with_rom(&RND).unwrap();
+        loaded.random = random::parse("script:09").unwrap();
+        load(&mut loaded, &data).unwrap();
+        assert_eq!(draws(&mut loaded, 2), [9, 9]);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::chip8::{Chip8State, PALETTE, PLANES};
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// RGB colours for each pixel value, like `chip8::PALETTE`.
+pub type Palette = [[u8; 3]; 1 << PLANES];
+
+/// Parse a palette of comma separated `RRGGBB` colours, for pixel values 0 up to 3. Only the
+/// first two are required; the XO-CHIP colours default to `PALETTE`'s.
+pub fn parse_palette(text: &str) -> Option<Palette> {
+    let colours: Vec<&str> = text.split(',').map(str::trim).collect();
+    if colours.len() < 2 || colours.len() > PALETTE.len() {
+        return None;
+    }
+
+    let mut palette = PALETTE;
+    for (entry, colour) in palette.iter_mut().zip(colours) {
+        let colour = colour.strip_prefix('#').unwrap_or(colour);
+        if colour.len() != 6 || !colour.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let rgb = u32::from_str_radix(colour, 16).ok()?;
+        *entry = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+    }
+
+    Some(palette)
+}
+
+/// An RGB image of the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// Three bytes per pixel, row by row.
+    pub data: Vec<u8>,
+}
+
+/// Render the visible screen with each CHIP-8 pixel drawn as a `scale` by `scale` square.
+pub fn render(chip8: &Chip8State, scale: u32, palette: &Palette) -> Image {
+    let scale = scale.max(1) as usize;
+    let width = chip8.screen_width();
+    let height = chip8.screen_height();
+
+    let mut data = Vec::with_capacity(width * height * scale * scale * 3);
+    for row in &chip8.video_buf[..height] {
+        let start = data.len();
+        for &pixel in &row[..width] {
+            for _ in 0..scale {
+                data.extend_from_slice(&palette[pixel as usize]);
+            }
+        }
+        // The rest of the square is copies of the first line.
+        for _ in 1..scale {
+            data.extend_from_within(start..start + width * scale * 3);
+        }
+    }
+
+    Image {
+        width: (width * scale) as u32,
+        height: (height * scale) as u32,
+        data,
+    }
+}
+
+/// Write the visible screen to a PNG file, rendered as by `render`.
+pub fn write_png<P: AsRef<Path>>(
+    chip8: &Chip8State,
+    path: P,
+    scale: u32,
+    palette: &Palette,
+) -> io::Result<()> {
+    write_png_image(&render(chip8, scale, palette), path)
+}
+
+pub fn write_png_image<P: AsRef<Path>>(image: &Image, path: P) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.data)?;
+
+    Ok(())
+}
+
+/// A file name for a capture of `rom` made at `time`, such as `pong-20261018-142501.png`. The
+/// time is in UTC.
+pub fn file_name(rom: &Path, time: SystemTime, extension: &str) -> String {
+    let stem = rom
+        .file_stem()
+        .map_or("screen".into(), |stem| stem.to_string_lossy());
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let (year, month, day) = civil_date(seconds / 86400);
+    let time_of_day = seconds % 86400;
+
+    format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}.{}",
+        stem,
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        extension
+    )
+}
+
+// The Gregorian date `days` after 1970-01-01, using Howard Hinnant's `civil_from_days`.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn file_name_has_rom_and_utc_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_792_333_501);
+        assert_eq!(
+            file_name(Path::new("roms/pong.ch8"), time, "png"),
+            "pong-20261018-142501.png"
+        );
+        assert_eq!(
+            file_name(Path::new("a"), UNIX_EPOCH, "gif"),
+            "a-19700101-000000.gif"
+        );
+        // A leap day.
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(
+            file_name(Path::new("a"), time, "png"),
+            "a-20000229-000000.png"
+        );
+    }
+
+    #[test]
+    fn render_scales_pixels() {
+        let mut chip8 = Chip8State::new();
+        chip8.video_buf[0][1] = 1;
+
+        let image = render(&chip8, 3, &PALETTE);
+        assert_eq!((image.width, image.height), (64 * 3, 32 * 3));
+        assert_eq!(image.data.len(), 64 * 3 * 32 * 3 * 3);
+
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * image.width as usize + x) * 3;
+            &image.data[offset..offset + 3]
+        };
+        assert_eq!(pixel(2, 0), PALETTE[0]);
+        assert_eq!(pixel(3, 0), PALETTE[1]);
+        assert_eq!(pixel(5, 2), PALETTE[1]);
+        assert_eq!(pixel(6, 2), PALETTE[0]);
+        assert_eq!(pixel(3, 3), PALETTE[0]);
+    }
+
+    #[test]
+    fn palettes() {
+        let palette = parse_palette("#102030,FFFFFF").unwrap();
+        assert_eq!(palette[0], [0x10, 0x20, 0x30]);
+        assert_eq!(palette[1], [0xFF, 0xFF, 0xFF]);
+        assert_eq!(palette[2], PALETTE[2]);
+
+        assert!(parse_palette("102030").is_none());
+        assert!(parse_palette("102030,GGGGGG").is_none());
+        assert!(parse_palette("1,2,3,4,5").is_none());
+    }
+}
@@ -0,0 +1,393 @@
+use crate::chip8::Chip8State;
+use crate::instruction::Instruction;
+
+use serde_json::json;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Aligned columns for reading.
+    Text,
+    /// One row per instruction with a header, in hex.
+    Csv,
+    /// One JSON object per line.
+    Json,
+}
+
+impl TraceFormat {
+    pub const NAMES: [&'static str; 3] = ["text", "csv", "json"];
+
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Some(TraceFormat::Text),
+            "csv" => Some(TraceFormat::Csv),
+            "json" | "jsonl" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A broad grouping of instructions, for filtering traces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeClass {
+    /// Jumps, calls, returns and `00FD`.
+    Flow,
+    /// Conditional skips on registers.
+    Skip,
+    /// Loads into registers and I, including font addresses.
+    Load,
+    /// Arithmetic and logic on registers, I and `CXNN`.
+    Arithmetic,
+    /// Reads and writes of memory at I.
+    Memory,
+    Display,
+    /// Key skips and `FX0A`.
+    Input,
+    Timer,
+    Sound,
+}
+
+impl OpcodeClass {
+    pub const NAMES: [&'static str; 9] = [
+        "flow", "skip", "load", "arith", "memory", "display", "input", "timer", "sound",
+    ];
+
+    pub fn from_name(name: &str) -> Option<OpcodeClass> {
+        match name.to_ascii_lowercase().as_str() {
+            "flow" => Some(OpcodeClass::Flow),
+            "skip" => Some(OpcodeClass::Skip),
+            "load" => Some(OpcodeClass::Load),
+            "arith" | "arithmetic" => Some(OpcodeClass::Arithmetic),
+            "memory" | "mem" => Some(OpcodeClass::Memory),
+            "display" | "draw" => Some(OpcodeClass::Display),
+            "input" | "keys" => Some(OpcodeClass::Input),
+            "timer" | "timers" => Some(OpcodeClass::Timer),
+            "sound" | "audio" => Some(OpcodeClass::Sound),
+            _ => None,
+        }
+    }
+
+    pub fn of(instruction: Instruction) -> OpcodeClass {
+        match instruction {
+            Instruction::Sys(_)
+            | Instruction::Ret
+            | Instruction::Exit
+            | Instruction::Jp(_)
+            | Instruction::Call(_)
+            | Instruction::JpV0(_) => OpcodeClass::Flow,
+            Instruction::SeImm { .. }
+            | Instruction::SneImm { .. }
+            | Instruction::SeReg { .. }
+            | Instruction::SneReg { .. } => OpcodeClass::Skip,
+            Instruction::LdImm { .. }
+            | Instruction::LdReg { .. }
+            | Instruction::LdI(_)
+            | Instruction::LdILong(_)
+            | Instruction::LdFVx(_)
+            | Instruction::LdHfVx(_) => OpcodeClass::Load,
+            Instruction::AddImm { .. }
+            | Instruction::Or { .. }
+            | Instruction::And { .. }
+            | Instruction::Xor { .. }
+            | Instruction::AddReg { .. }
+            | Instruction::Sub { .. }
+            | Instruction::Shr { .. }
+            | Instruction::Subn { .. }
+            | Instruction::Shl { .. }
+            | Instruction::AddIVx(_)
+            | Instruction::Rnd { .. } => OpcodeClass::Arithmetic,
+            Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LdBVx(_)
+            | Instruction::LdIVx(_)
+            | Instruction::LdVxI(_)
+            | Instruction::LdRVx(_)
+            | Instruction::LdVxR(_) => OpcodeClass::Memory,
+            Instruction::Cls
+            | Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Low
+            | Instruction::High
+            | Instruction::Drw { .. }
+            | Instruction::Plane(_) => OpcodeClass::Display,
+            Instruction::Skp(_) | Instruction::Sknp(_) | Instruction::LdVxK(_) => {
+                OpcodeClass::Input
+            }
+            Instruction::LdVxDt(_) | Instruction::LdDtVx(_) | Instruction::LdStVx(_) => {
+                OpcodeClass::Timer
+            }
+            Instruction::Audio | Instruction::Pitch(_) => OpcodeClass::Sound,
+        }
+    }
+}
+
+/// Which instructions to trace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only trace instructions at addresses in this inclusive range.
+    pub addresses: Option<(u16, u16)>,
+    /// Only trace these classes of instruction. Empty traces every class, and unknown opcodes
+    /// are always traced.
+    pub classes: Vec<OpcodeClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, instruction: Option<Instruction>) -> bool {
+        if let Some((start, end)) = self.addresses {
+            if pc < start || pc > end {
+                return false;
+            }
+        }
+
+        match instruction {
+            Some(instruction) if !self.classes.is_empty() => {
+                self.classes.contains(&OpcodeClass::of(instruction))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Writes a line for each instruction executed, before it runs.
+pub struct Tracer {
+    pub format: TraceFormat,
+    pub filter: TraceFilter,
+    /// Whether to include V0-VF, I, SP and the timers on each line.
+    pub registers: bool,
+    out: Box<dyn Write + Send>,
+    lines: u64,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write + Send>, format: TraceFormat) -> Tracer {
+        Tracer {
+            format,
+            filter: TraceFilter::default(),
+            registers: false,
+            out,
+            lines: 0,
+        }
+    }
+
+    pub fn stdout(format: TraceFormat) -> Tracer {
+        Tracer::new(Box::new(io::stdout()), format)
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file)), format))
+    }
+
+    /// Number of lines written so far, not counting the CSV header.
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Trace the instruction at `pc`, whose first word is `opcode`, if the filter allows it.
+    pub fn record(
+        &mut self,
+        chip8: &Chip8State,
+        opcode: u16,
+        instruction: Option<Instruction>,
+    ) -> io::Result<()> {
+        if !self.filter.matches(chip8.pc, instruction) {
+            return Ok(());
+        }
+
+        let opcode = match instruction {
+            Some(Instruction::LdILong(address)) => format!("{:04X}{:04X}", opcode, address),
+            _ => format!("{:04X}", opcode),
+        };
+        let text = match instruction {
+            Some(instruction) => instruction.to_string(),
+            None => "unknown opcode".to_string(),
+        };
+
+        match self.format {
+            TraceFormat::Text => self.write_text(chip8, &opcode, &text)?,
+            TraceFormat::Csv => self.write_csv(chip8, &opcode, &text)?,
+            TraceFormat::Json => self.write_json(chip8, &opcode, &text)?,
+        }
+        self.lines += 1;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn write_text(&mut self, chip8: &Chip8State, opcode: &str, text: &str) -> io::Result<()> {
+        write!(
+            self.out,
+            "{:>10}  {:#05X}  {:<8}  {:<22}",
+            chip8.cycles, chip8.pc, opcode, text
+        )?;
+
+        if self.registers {
+            for (x, value) in chip8.registers.iter().enumerate() {
+                write!(self.out, " V{:X}={:02X}", x, value)?;
+            }
+            write!(
+                self.out,
+                " I={:04X} SP={:X} DT={:02X} ST={:02X}",
+                chip8.i_register, chip8.sp, chip8.delay_timer, chip8.sound_timer
+            )?;
+        }
+
+        writeln!(self.out)
+    }
+
+    fn write_csv(&mut self, chip8: &Chip8State, opcode: &str, text: &str) -> io::Result<()> {
+        if self.lines == 0 {
+            write!(self.out, "cycle,pc,opcode,instruction")?;
+            if self.registers {
+                for x in 0..16 {
+                    write!(self.out, ",v{:x}", x)?;
+                }
+                write!(self.out, ",i,sp,dt,st")?;
+            }
+            writeln!(self.out)?;
+        }
+
+        // Mnemonics contain commas but never quotes.
+        write!(
+            self.out,
+            "{},{:04X},{},\"{}\"",
+            chip8.cycles, chip8.pc, opcode, text
+        )?;
+
+        if self.registers {
+            for value in chip8.registers.iter() {
+                write!(self.out, ",{:02X}", value)?;
+            }
+            write!(
+                self.out,
+                ",{:04X},{},{:02X},{:02X}",
+                chip8.i_register, chip8.sp, chip8.delay_timer, chip8.sound_timer
+            )?;
+        }
+
+        writeln!(self.out)
+    }
+
+    fn write_json(&mut self, chip8: &Chip8State, opcode: &str, text: &str) -> io::Result<()> {
+        let mut line = json!({
+            "cycle": chip8.cycles,
+            "pc": chip8.pc,
+            "opcode": opcode,
+            "instruction": text,
+        });
+
+        if self.registers {
+            line["v"] = json!(chip8.registers);
+            line["i"] = json!(chip8.i_register);
+            line["sp"] = json!(chip8.sp);
+            line["dt"] = json!(chip8.delay_timer);
+            line["st"] = json!(chip8.sound_timer);
+        }
+
+        writeln!(self.out, "{}", line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    use std::sync::{Arc, Mutex};
+
+    // LD V0, 5; LD I, 0x300; LD I, LONG 0x1234; JP 0x208
+    const ROM: [u8; 10] = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x12, 0x08];
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Trace `steps` instructions of the ROM, returning the output.
+    fn trace(format: TraceFormat, registers: bool, filter: TraceFilter, steps: usize) -> String {
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()), format);
+        tracer.registers = registers;
+        tracer.filter = filter;
+
+        let mut chip8 = Chip8State::with_rom(&ROM).unwrap();
+        chip8.tracer = Some(tracer);
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        let bytes = out.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn csv_with_registers() {
+        let csv = trace(TraceFormat::Csv, true, TraceFilter::default(), 4);
+        let rows: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(rows.len(), 5);
+        assert!(rows[0].starts_with("cycle,pc,opcode,instruction,v0,v1,"));
+        assert!(rows[0].ends_with(",vf,i,sp,dt,st"));
+
+        let fields: Vec<&str> = rows[3].split(",\"").collect();
+        let before: Vec<&str> = fields[0].split(',').collect();
+        let after: Vec<&str> = fields[1].split("\",").nth(1).unwrap().split(',').collect();
+        assert_eq!(&before[1..], ["0204", "F0001234"]);
+        assert_eq!(after.len(), 20);
+        assert_eq!((after[0], after[16]), ("05", "0300"));
+    }
+
+    #[test]
+    fn json_lines() {
+        let json = trace(TraceFormat::Json, true, TraceFilter::default(), 3);
+        let lines: Vec<Value> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["pc"], 0x202);
+        assert_eq!(lines[1]["opcode"], "A300");
+        assert_eq!(lines[1]["v"][0], 5);
+        assert_eq!(lines[2]["i"], 0x300);
+
+        let plain = trace(TraceFormat::Json, false, TraceFilter::default(), 1);
+        assert!(!plain.contains("\"v\""));
+    }
+
+    #[test]
+    fn filters() {
+        let addresses = TraceFilter {
+            addresses: Some((0x202, 0x204)),
+            classes: Vec::new(),
+        };
+        let text = trace(TraceFormat::Text, false, addresses, 4);
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.lines().all(|line| line.contains("0x20")));
+
+        let jumps = TraceFilter {
+            addresses: None,
+            classes: vec![OpcodeClass::Flow],
+        };
+        let text = trace(TraceFormat::Text, false, jumps, 6);
+        assert_eq!(text.lines().count(), 3);
+    }
+}
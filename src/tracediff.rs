@@ -0,0 +1,536 @@
+use crate::chip8::Chip8State;
+use crate::debugger;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+
+use serde_json::Value;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+#[derive(Debug)]
+pub enum TraceDiffError {
+    Parse { line: usize, message: String },
+    Io(io::Error),
+}
+
+impl fmt::Display for TraceDiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceDiffError::Parse { line, message } => {
+                write!(f, "reference trace line {}: {}", line, message)
+            }
+            TraceDiffError::Io(err) => write!(f, "unable to read reference trace: {}", err),
+        }
+    }
+}
+
+impl Error for TraceDiffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceDiffError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceDiffError {
+    fn from(err: io::Error) -> TraceDiffError {
+        TraceDiffError::Io(err)
+    }
+}
+
+/// The machine just before an instruction executes.
+///
+/// Anything a reference trace leaves out is `None` and isn't compared.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Line of the reference trace this came from, or 0 for the emulator.
+    pub line: usize,
+    pub cycle: Option<u64>,
+    pub pc: u16,
+    /// In hex, with both words of `F000 NNNN`.
+    pub opcode: Option<String>,
+    pub registers: [Option<u8>; 16],
+    pub i: Option<u16>,
+}
+
+impl TraceEntry {
+    pub fn from_state(chip8: &Chip8State) -> TraceEntry {
+        let pc = chip8.pc as usize;
+        let opcode = match chip8.instruction_at(chip8.pc) {
+            Some(Instruction::LdILong(address)) => Some(format!("F000{:04X}", address)),
+            _ => chip8
+                .memory
+                .get(pc..pc + 2)
+                .map(|word| format!("{:02X}{:02X}", word[0], word[1])),
+        };
+
+        let mut registers = [None; 16];
+        for (entry, value) in registers.iter_mut().zip(chip8.registers.iter()) {
+            *entry = Some(*value);
+        }
+
+        TraceEntry {
+            line: 0,
+            cycle: Some(chip8.cycles),
+            pc: chip8.pc,
+            opcode,
+            registers,
+            i: Some(chip8.i_register),
+        }
+    }
+
+    /// Names of the fields of `self` that `actual` doesn't match.
+    pub fn differences(&self, actual: &TraceEntry) -> Vec<String> {
+        let mut fields = Vec::new();
+
+        if self.pc != actual.pc {
+            fields.push("pc".to_string());
+        }
+        if let Some(opcode) = &self.opcode {
+            if actual.opcode.as_ref() != Some(opcode) {
+                fields.push("opcode".to_string());
+            }
+        }
+        for (x, (expected, found)) in self.registers.iter().zip(&actual.registers).enumerate() {
+            if expected.is_some() && expected != found {
+                fields.push(format!("V{:X}", x));
+            }
+        }
+        if self.i.is_some() && self.i != actual.i {
+            fields.push("I".to_string());
+        }
+
+        fields
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cycle {
+            Some(cycle) => write!(f, "{:>10}  {:#05X}", cycle, self.pc)?,
+            None => write!(f, "{:>10}  {:#05X}", "", self.pc)?,
+        }
+        write!(f, "  {:<8}", self.opcode.as_deref().unwrap_or("????"))?;
+
+        for (x, value) in self.registers.iter().enumerate() {
+            if let Some(value) = value {
+                write!(f, " V{:X}={:02X}", x, value)?;
+            }
+        }
+        if let Some(i) = self.i {
+            write!(f, " I={:04X}", i)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a reference trace one step at a time.
+///
+/// The trace is either CSV with a header row, or one JSON object per line, as written by
+/// `trace::Tracer` with registers included. Only a `pc` column is required; `opcode`, `v0` to
+/// `vf` and `i` are compared when present, and `cycle` is only shown for context. CSV values
+/// are hex apart from the decimal cycle, as are JSON strings, while JSON numbers are taken as
+/// they are. Blank lines and lines starting with `#` are skipped.
+pub struct ReferenceTrace<R> {
+    lines: io::Lines<R>,
+    line: usize,
+    columns: Option<Vec<String>>,
+}
+
+impl<R: BufRead> ReferenceTrace<R> {
+    pub fn new(reader: R) -> ReferenceTrace<R> {
+        ReferenceTrace {
+            lines: reader.lines(),
+            line: 0,
+            columns: None,
+        }
+    }
+
+    fn error(&self, message: String) -> TraceDiffError {
+        TraceDiffError::Parse {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn parse_csv(&self, text: &str) -> Result<TraceEntry, TraceDiffError> {
+        let columns = self.columns.as_ref().expect("CSV header not read");
+        let values = split_csv(text);
+        if values.len() != columns.len() {
+            return Err(self.error(format!(
+                "expected {} columns, found {}",
+                columns.len(),
+                values.len()
+            )));
+        }
+
+        let mut entry = TraceEntry {
+            line: self.line,
+            ..TraceEntry::default()
+        };
+        for (column, value) in columns.iter().zip(values) {
+            let value = value.trim();
+            let hex = |max: u64| {
+                parse_hex(value)
+                    .filter(|number| *number <= max)
+                    .ok_or_else(|| self.error(format!("invalid {} '{}'", column, value)))
+            };
+
+            match column.as_str() {
+                "cycle" => {
+                    entry.cycle = Some(
+                        value
+                            .parse()
+                            .map_err(|_| self.error(format!("invalid cycle '{}'", value)))?,
+                    )
+                }
+                "pc" => entry.pc = hex(0xFFFF)? as u16,
+                "opcode" => entry.opcode = Some(value.to_ascii_uppercase()),
+                "i" => entry.i = Some(hex(0xFFFF)? as u16),
+                _ => {
+                    if let Some(x) = register(column) {
+                        entry.registers[x] = Some(hex(0xFF)? as u8);
+                    }
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn parse_json(&self, text: &str) -> Result<TraceEntry, TraceDiffError> {
+        let object: Value =
+            serde_json::from_str(text).map_err(|err| self.error(err.to_string()))?;
+        let number = |key: &str, max: u64| -> Result<Option<u64>, TraceDiffError> {
+            let value = match object.get(key) {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            let number = match value {
+                Value::Number(number) => number.as_u64(),
+                Value::String(text) => parse_hex(text),
+                _ => None,
+            };
+            match number {
+                Some(number) if number <= max => Ok(Some(number)),
+                _ => Err(self.error(format!("invalid {} {}", key, value))),
+            }
+        };
+
+        let mut entry = TraceEntry {
+            line: self.line,
+            cycle: number("cycle", u64::MAX)?,
+            pc: number("pc", 0xFFFF)?.ok_or_else(|| self.error("missing pc".to_string()))? as u16,
+            opcode: object
+                .get("opcode")
+                .and_then(Value::as_str)
+                .map(str::to_ascii_uppercase),
+            i: number("i", 0xFFFF)?.map(|i| i as u16),
+            ..TraceEntry::default()
+        };
+
+        if let Some(values) = object.get("v") {
+            let values = values
+                .as_array()
+                .filter(|values| values.len() == 16)
+                .ok_or_else(|| self.error("v must be an array of 16 registers".to_string()))?;
+            for (entry, value) in entry.registers.iter_mut().zip(values) {
+                match value.as_u64().filter(|value| *value <= 0xFF) {
+                    Some(value) => *entry = Some(value as u8),
+                    None => return Err(self.error(format!("invalid register {}", value))),
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+}
+
+impl<R: BufRead> Iterator for ReferenceTrace<R> {
+    type Item = Result<TraceEntry, TraceDiffError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.lines.next()? {
+                Ok(text) => text,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line += 1;
+
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            if text.starts_with('{') {
+                return Some(self.parse_json(text));
+            }
+            if self.columns.is_some() {
+                return Some(self.parse_csv(text));
+            }
+
+            let columns: Vec<String> = split_csv(text)
+                .iter()
+                .map(|column| column.trim().to_ascii_lowercase())
+                .collect();
+            if !columns.iter().any(|column| column == "pc") {
+                return Some(Err(self.error(
+                    "expected JSON lines or a CSV header with a pc column".to_string(),
+                )));
+            }
+            self.columns = Some(columns);
+        }
+    }
+}
+
+/// Where emulation first differs from the reference.
+#[derive(Debug)]
+pub struct Divergence {
+    /// Steps that matched before this one.
+    pub step: u64,
+    pub expected: TraceEntry,
+    pub actual: TraceEntry,
+    /// Names of the fields that differ.
+    pub fields: Vec<String>,
+    /// The steps just before, which matched, oldest first.
+    pub before: Vec<TraceEntry>,
+    /// The reference steps that follow.
+    pub after: Vec<TraceEntry>,
+    /// `debugger::dump_state` of the emulator at this step.
+    pub state: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Diverged at step {} (reference line {}), mismatched {}",
+            self.step,
+            self.expected.line,
+            self.fields.join(", ")
+        )?;
+
+        for entry in &self.before {
+            writeln!(f, "  {}", entry)?;
+        }
+        writeln!(f, "- {}", self.expected)?;
+        writeln!(f, "+ {}", self.actual)?;
+        for entry in &self.after {
+            writeln!(f, "  {}", entry)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Reference state:")?;
+        writeln!(f, "{}", self.expected)?;
+        writeln!(f, "Emulator state:")?;
+        write!(f, "{}", self.state)
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    /// Every step of the reference matched.
+    Matched {
+        steps: u64,
+    },
+    Diverged(Box<Divergence>),
+    /// The emulator stopped with an error while the reference went on.
+    Failed {
+        step: u64,
+        error: Chip8Error,
+        before: Vec<TraceEntry>,
+    },
+}
+
+/// Run `chip8` one step per entry of `reference`, stopping at the first step that differs.
+///
+/// `context` is how many steps either side of a divergence to keep.
+pub fn compare<R: BufRead>(
+    chip8: &mut Chip8State,
+    reference: R,
+    context: usize,
+) -> Result<Outcome, TraceDiffError> {
+    let mut reference = ReferenceTrace::new(reference);
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut step = 0;
+
+    while let Some(expected) = reference.next() {
+        let expected = expected?;
+        let actual = TraceEntry::from_state(chip8);
+
+        let fields = expected.differences(&actual);
+        if !fields.is_empty() {
+            let after = reference.by_ref().take(context).collect::<Result<_, _>>()?;
+            return Ok(Outcome::Diverged(Box::new(Divergence {
+                step,
+                expected,
+                actual,
+                fields,
+                before: before.into_iter().collect(),
+                after,
+                state: debugger::dump_state(chip8),
+            })));
+        }
+
+        before.push_back(expected);
+        if before.len() > context {
+            before.pop_front();
+        }
+
+        if let Err(error) = chip8.step() {
+            return Ok(Outcome::Failed {
+                step,
+                error,
+                before: before.into_iter().collect(),
+            });
+        }
+        step += 1;
+    }
+
+    Ok(Outcome::Matched { steps: step })
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+fn register(column: &str) -> Option<usize> {
+    let digit = column.strip_prefix('v')?;
+    if digit.len() == 1 {
+        usize::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+// Splits a CSV row on commas outside double quotes, removing the quotes.
+fn split_csv(row: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+
+    for c in row.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{TraceFormat, Tracer};
+
+    use std::sync::{Arc, Mutex};
+
+    // LD V0, 5; RND V1, 0xFF; LD I, 0x300; ADD V0, 1; JP 0x206
+    const ROM: [u8; 10] = [0x60, 0x05, 0xC1, 0xFF, 0xA3, 0x00, 0x70, 0x01, 0x12, 0x06];
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn machine(seed: u64) -> Chip8State {
+        let mut chip8 = Chip8State::with_rom(&ROM).unwrap();
+        chip8.seed_random(seed);
+        chip8
+    }
+
+    // A reference trace of `steps` steps with registers, as `Tracer` writes it.
+    fn reference(format: TraceFormat, seed: u64, steps: usize) -> String {
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()), format);
+        tracer.registers = true;
+
+        let mut chip8 = machine(seed);
+        chip8.tracer = Some(tracer);
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+
+        let bytes = out.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn diverged(outcome: Outcome) -> Divergence {
+        match outcome {
+            Outcome::Diverged(divergence) => *divergence,
+            other => panic!("expected a divergence, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn own_traces_match() {
+        for &format in &[TraceFormat::Csv, TraceFormat::Json] {
+            let trace = reference(format, 7, 20);
+            let outcome = compare(&mut machine(7), trace.as_bytes(), 3).unwrap();
+            assert!(
+                matches!(outcome, Outcome::Matched { steps: 20 }),
+                "{:?}",
+                outcome
+            );
+        }
+    }
+
+    #[test]
+    fn finds_a_changed_register() {
+        let trace = reference(TraceFormat::Json, 7, 20);
+        let mut lines: Vec<String> = trace.lines().map(str::to_string).collect();
+        let mut entry: Value = serde_json::from_str(&lines[10]).unwrap();
+        entry["v"][0] = Value::from(0xEE);
+        lines[10] = entry.to_string();
+
+        let divergence =
+            diverged(compare(&mut machine(7), lines.join("\n").as_bytes(), 3).unwrap());
+        assert_eq!(divergence.step, 10);
+        assert_eq!(divergence.fields, ["V0"]);
+        assert_eq!(divergence.expected.line, 11);
+        assert_eq!(divergence.expected.registers[0], Some(0xEE));
+        assert_eq!(divergence.before.len(), 3);
+        assert_eq!(divergence.after.len(), 3);
+    }
+
+    #[test]
+    fn other_seeds_diverge_after_rnd() {
+        let trace = reference(TraceFormat::Csv, 7, 20);
+        let divergence = diverged(compare(&mut machine(8), trace.as_bytes(), 3).unwrap());
+        assert_eq!(divergence.step, 2);
+        assert_eq!(divergence.fields, ["V1"]);
+        assert_eq!(divergence.before.len(), 2);
+        assert!(divergence.to_string().contains("mismatched V1"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |trace: &str| match compare(&mut machine(7), trace.as_bytes(), 0) {
+            Err(TraceDiffError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, found {:?}", other),
+        };
+
+        assert_eq!(error("cycle,opcode\n"), 1);
+        assert_eq!(error("# comment\npc,v0\n0200,05,06\n"), 3);
+        assert_eq!(error("{\"pc\": 512, \"v\": [1]}"), 1);
+    }
+}